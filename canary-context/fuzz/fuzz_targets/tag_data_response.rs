@@ -0,0 +1,10 @@
+#![no_main]
+
+use canary_context::response;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|body: &[u8]| {
+    let _ = response::parse_tag_data(body, None);
+    let _ = response::parse_tag_data(body, Some(64));
+    let _ = response::check_status("getTagData", 200, body);
+});
@@ -0,0 +1,103 @@
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+const REDACTED: &str = "[REDACTED]";
+const SECRET_KEYS: [&str; 4] = ["apiToken", "liveDataToken", "password", "userToken"];
+
+/// A secret key with its value, as a JSON member (possibly cut off at the
+/// end of the text) or as a query or form parameter.
+static SECRET_VALUES: LazyLock<Regex> = LazyLock::new(|| {
+    let keys = SECRET_KEYS.join("|");
+    Regex::new(&format!(r#""({keys})"(\s*:\s*)"(?:[^"\\]|\\.?)*(?:"|$)|\b({keys})=[^&\s"]*"#)).expect("secret pattern is valid")
+});
+
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    endpoint: &'a str,
+    correlation_id: &'a str,
+    parameters: Value,
+    duration_ms: u128,
+    status: Option<u16>,
+    rows: Option<usize>,
+    error: Option<String>,
+}
+
+/// Append-only JSONL log with one record per API call. A disabled log
+/// accepts records and drops them.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    pub fn disabled() -> Self {
+        AuditLog { file: None }
+    }
+
+    pub fn open(path: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let file = match path {
+            Some(path) => Some(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)),
+            None => None,
+        };
+        Ok(AuditLog { file })
+    }
+
+    pub fn record(&self, endpoint: &str, correlation_id: &str, payload: &Value, elapsed: Duration, status: Option<u16>, outcome: Result<usize, String>) -> Result<(), Box<dyn Error>> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        let (rows, error) = match outcome {
+            Ok(rows) => (Some(rows), None),
+            Err(error) => (None, Some(error)),
+        };
+        let record = AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            endpoint,
+            correlation_id,
+            parameters: redact(payload),
+            duration_ms: elapsed.as_millis(),
+            status,
+            rows,
+            error,
+        };
+
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        let mut file = file.lock().map_err(|_| "audit log lock poisoned")?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+pub(crate) fn redact(payload: &Value) -> Value {
+    match payload {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let value = if SECRET_KEYS.contains(&key.as_str()) { Value::String(REDACTED.to_string()) } else { redact(value) };
+                    (key.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(redact).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Blanks secret values in text that may echo a request, such as an error
+/// body quoted from the server, whether or not it is complete JSON.
+pub(crate) fn redact_text(text: &str) -> Cow<'_, str> {
+    SECRET_VALUES.replace_all(text, |captures: &regex::Captures| match captures.get(1) {
+        Some(key) => format!("\"{}\"{}\"{}\"", key.as_str(), &captures[2], REDACTED),
+        None => format!("{}={}", &captures[3], REDACTED),
+    })
+}
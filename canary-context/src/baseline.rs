@@ -0,0 +1,198 @@
+use canary_context::response::TagContext;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+
+/// The identifying fields of a tag that are expected to stay fixed between
+/// runs. Timestamps are left out because they move with every sample.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaselineTag {
+    pub tag_name: String,
+    pub historian_item_id: Option<String>,
+    pub source_item_id: Option<String>,
+}
+
+/// An approved tag inventory, written by `baseline write` and compared
+/// against the live historian by `baseline check`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Baseline {
+    pub created_at: String,
+    pub server: String,
+    pub tags: Vec<BaselineTag>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Deviation {
+    Missing(String),
+    Unexpected(String),
+    Changed { tag_name: String, field: &'static str, expected: Option<String>, actual: Option<String> },
+}
+
+impl fmt::Display for Deviation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Deviation::Missing(tag_name) => write!(f, "missing:    {}", tag_name),
+            Deviation::Unexpected(tag_name) => write!(f, "unexpected: {}", tag_name),
+            Deviation::Changed { tag_name, field, expected, actual } => write!(
+                f,
+                "changed:    {} {} expected '{}', found '{}'",
+                tag_name,
+                field,
+                expected.as_deref().unwrap_or(""),
+                actual.as_deref().unwrap_or("")
+            ),
+        }
+    }
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+impl Deviation {
+    /// The text line in the terminal colors of a diff: removed tags red,
+    /// added tags green, changed fields yellow.
+    pub fn colored(&self) -> String {
+        let color = match self {
+            Deviation::Missing(_) => RED,
+            Deviation::Unexpected(_) => GREEN,
+            Deviation::Changed { .. } => YELLOW,
+        };
+        format!("{}{}{}", color, self, RESET)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Deviation::Missing(tag_name) => serde_json::json!({ "kind": "missing", "tagName": tag_name }),
+            Deviation::Unexpected(tag_name) => serde_json::json!({ "kind": "unexpected", "tagName": tag_name }),
+            Deviation::Changed { tag_name, field, expected, actual } => {
+                serde_json::json!({ "kind": "changed", "tagName": tag_name, "field": field, "expected": expected, "actual": actual })
+            }
+        }
+    }
+}
+
+impl Baseline {
+    pub fn from_contexts(server: &str, data: &[TagContext]) -> Self {
+        let mut tags: Vec<BaselineTag> = data
+            .iter()
+            .map(|item| BaselineTag {
+                tag_name: item.tag_name().to_string(),
+                historian_item_id: item.details().historian_item_id().map(String::from),
+                source_item_id: item.details().source_item_id().map(String::from),
+            })
+            .collect();
+        tags.sort_by(|a, b| a.tag_name.cmp(&b.tag_name));
+        tags.dedup();
+
+        Baseline { created_at: chrono::Utc::now().to_rfc3339(), server: server.to_string(), tags }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path).map_err(|e| format!("cannot open baseline {}: {}", path, e))?;
+        serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("invalid baseline {}: {}", path, e).into())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// Deviations of `live` from this baseline. Tags are matched by `key`,
+    /// and a matched tag deviates in each of `compare` whose value differs.
+    /// Tags without a value for the key cannot be matched and show up as
+    /// missing or unexpected. Ordered by key.
+    pub fn check(&self, live: &Baseline, key: Field, compare: &[Field]) -> Vec<Deviation> {
+        let (expected, expected_unkeyed) = by_key(&self.tags, key);
+        let (actual, actual_unkeyed) = by_key(&live.tags, key);
+        let mut keys: Vec<&str> = expected.keys().chain(actual.keys()).copied().collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut deviations = Vec::new();
+        for key in keys {
+            match (expected.get(key), actual.get(key)) {
+                (Some(expected), None) => deviations.push(Deviation::Missing(expected.tag_name.clone())),
+                (None, Some(actual)) => deviations.push(Deviation::Unexpected(actual.tag_name.clone())),
+                (Some(expected), Some(actual)) => {
+                    for field in compare {
+                        let (expected_value, actual_value) = (field.value(expected), field.value(actual));
+                        if expected_value != actual_value {
+                            deviations.push(Deviation::Changed {
+                                tag_name: expected.tag_name.clone(),
+                                field: field.name(),
+                                expected: expected_value.map(String::from),
+                                actual: actual_value.map(String::from),
+                            });
+                        }
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        deviations.extend(expected_unkeyed.into_iter().map(Deviation::Missing));
+        deviations.extend(actual_unkeyed.into_iter().map(Deviation::Unexpected));
+        deviations
+    }
+}
+
+fn by_key(tags: &[BaselineTag], key: Field) -> (BTreeMap<&str, &BaselineTag>, Vec<String>) {
+    let mut keyed = BTreeMap::new();
+    let mut unkeyed = Vec::new();
+    for tag in tags {
+        match key.value(tag) {
+            Some(value) => {
+                keyed.insert(value, tag);
+            }
+            None => unkeyed.push(tag.tag_name.clone()),
+        }
+    }
+    (keyed, unkeyed)
+}
+
+/// A baseline field that tags can be matched or compared on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    TagName,
+    HistorianItemId,
+    SourceItemId,
+}
+
+impl Field {
+    pub const ALL: [Field; 3] = [Field::TagName, Field::HistorianItemId, Field::SourceItemId];
+
+    /// The field's CSV column name, as used on the command line.
+    pub fn column(self) -> &'static str {
+        match self {
+            Field::TagName => "tag_name",
+            Field::HistorianItemId => "historian_item_id",
+            Field::SourceItemId => "source_item_id",
+        }
+    }
+
+    pub fn from_column(column: &str) -> Option<Self> {
+        Field::ALL.into_iter().find(|field| field.column() == column)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::TagName => "tagName",
+            Field::HistorianItemId => "historianItemId",
+            Field::SourceItemId => "sourceItemId",
+        }
+    }
+
+    fn value(self, tag: &BaselineTag) -> Option<&str> {
+        match self {
+            Field::TagName => Some(&tag.tag_name),
+            Field::HistorianItemId => tag.historian_item_id.as_deref(),
+            Field::SourceItemId => tag.source_item_id.as_deref(),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::output::{DataRecord, Record};
+use canary_context::response;
+use percent_encoding::percent_decode_str;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use std::error::Error;
+
+/// One row of ClickHouse's JSONEachRow input, with the CSV column names.
+#[derive(Serialize)]
+struct Row<'a> {
+    tag_name: &'a str,
+    historian_item_id: Option<&'a str>,
+    source_item_id: Option<&'a str>,
+    oldest_time_stamp: &'a str,
+    latest_time_stamp: &'a str,
+    retrieved_at: &'a str,
+    historian: Option<&'a str>,
+    row_id: Option<&'a str>,
+    alias: Option<&'a str>,
+    dataset: Option<&'a str>,
+    unit: Option<&'a str>,
+}
+
+impl<'a> From<&'a Record<'a>> for Row<'a> {
+    fn from(record: &'a Record<'a>) -> Self {
+        let details = record.tag_context;
+        Row {
+            tag_name: &record.tag_name,
+            historian_item_id: details.historian_item_id(),
+            source_item_id: details.source_item_id(),
+            oldest_time_stamp: details.oldest_time_stamp(),
+            latest_time_stamp: details.latest_time_stamp(),
+            retrieved_at: record.retrieved_at,
+            historian: record.historian,
+            row_id: record.row_id.as_deref(),
+            alias: record.alias,
+            dataset: record.dataset,
+            unit: record.unit,
+        }
+    }
+}
+
+/// One sample of a `data` export as a JSONEachRow row, with the CSV column
+/// names. The value is sent as the server returned it.
+#[derive(Serialize)]
+struct SampleRow<'a> {
+    tag_name: &'a str,
+    timestamp: &'a str,
+    value: &'a serde_json::Value,
+    quality: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregate: Option<&'a str>,
+}
+
+impl<'a> From<&'a DataRecord<'a>> for SampleRow<'a> {
+    fn from(record: &'a DataRecord<'a>) -> Self {
+        SampleRow {
+            tag_name: &record.tag_name,
+            timestamp: record.sample.timestamp(),
+            value: record.sample.value(),
+            quality: record.sample.quality(),
+            quality_name: record.quality_name.as_deref(),
+            aggregate: record.aggregate,
+        }
+    }
+}
+
+/// Inserts records into `table` through the ClickHouse HTTP interface, one
+/// request per batch. Async inserts let the server buffer small batches;
+/// waiting for them keeps insert errors visible to the caller. Columns the
+/// table does not have are skipped. Returns the number of batches sent.
+pub async fn insert(client: &Client, url: &str, table: &str, records: &[Record<'_>], batch_size: usize) -> Result<usize, Box<dyn Error>> {
+    insert_rows(client, url, table, records, Row::from, batch_size).await
+}
+
+/// Inserts the samples of a `data` export into `table`; see `insert`.
+pub async fn insert_data(client: &Client, url: &str, table: &str, records: &[DataRecord<'_>], batch_size: usize) -> Result<usize, Box<dyn Error>> {
+    insert_rows(client, url, table, records, SampleRow::from, batch_size).await
+}
+
+async fn insert_rows<'a, T, R: Serialize>(client: &Client, url: &str, table: &str, records: &'a [T], row: impl Fn(&'a T) -> R, batch_size: usize) -> Result<usize, Box<dyn Error>> {
+    if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+        return Err(format!("invalid ClickHouse table name: {}", table).into());
+    }
+    let query = format!("INSERT INTO {} FORMAT JSONEachRow", table);
+    let (url, credentials) = split_credentials(url)?;
+
+    let mut batches = 0;
+    for batch in records.chunks(batch_size.max(1)) {
+        let mut body = Vec::new();
+        for record in batch {
+            serde_json::to_writer(&mut body, &row(record))?;
+            body.push(b'\n');
+        }
+        let mut request = client.post(url.clone());
+        if let Some((user, password)) = &credentials {
+            request = request.basic_auth(user, password.as_ref());
+        }
+        let response = request
+            .query(&[("query", query.as_str()), ("async_insert", "1"), ("wait_for_async_insert", "1"), ("input_format_skip_unknown_fields", "1")])
+            .body(body)
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        response::check_status("ClickHouse insert", status, &response.bytes().await?)?;
+        batches += 1;
+    }
+    Ok(batches)
+}
+
+/// A user and optional password.
+type Credentials = (String, Option<String>);
+
+/// Moves the user and password out of the URL, so they are sent as basic
+/// auth and never appear in an error that quotes the URL.
+fn split_credentials(url: &str) -> Result<(Url, Option<Credentials>), Box<dyn Error>> {
+    let mut url = Url::parse(url).map_err(|e| format!("invalid --clickhouse_url: {}", e))?;
+    let decode = |text: &str| percent_decode_str(text).decode_utf8_lossy().into_owned();
+    let credentials = match (url.username(), url.password()) {
+        ("", None) => None,
+        (user, password) => Some((decode(user), password.map(decode))),
+    };
+    url.set_username("").and_then(|()| url.set_password(None)).map_err(|()| "invalid --clickhouse_url: it cannot carry credentials")?;
+    Ok((url, credentials))
+}
@@ -0,0 +1,803 @@
+use crate::audit::{self, AuditLog};
+use crate::request::{BrowseRequest, TagDataRequest};
+use crate::response::{self, ResponseError, TagContext, TagData, TagDataPage};
+use crate::secret::Secret;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::Client;
+use rand::Rng;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use tokio::task::AbortHandle;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+use uuid::Uuid;
+
+const ERROR_BODY_LIMIT: usize = 4096;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_API_VERSION: &str = "api/v2";
+const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_CONTEXT_BATCH_SIZE: usize = 500;
+const DEFAULT_CONCURRENCY: usize = 4;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+const RUN_ID_HEADER: &str = "X-Run-ID";
+const CORRELATION_ID_HEADER: &str = "X-Correlation-ID";
+
+/// Client for the Canary Views API of one server. The API token is sent
+/// with every call and never printed.
+///
+/// ```no_run
+/// use canary_context::request::BrowseRequest;
+/// use canary_context::CanaryClient;
+///
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = CanaryClient::builder("my-token").server("https://canary:55236").connect().await?;
+/// let tags = client.browse_tags(&BrowseRequest::builder().deep(true).build()).await?;
+/// let contexts = client.get_tag_context(&tags).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CanaryClient {
+    transport: Arc<Transport>,
+    server: String,
+    token: Token,
+    max_response_size: Option<u64>,
+    context_batch_size: usize,
+    concurrency: usize,
+    retries: u32,
+    retry_delay: Duration,
+    on_retry: OnRetry,
+    skipped_servers: Vec<String>,
+    keep_alive: Option<AbortHandle>,
+}
+
+impl CanaryClient {
+    pub fn builder(api_token: impl Into<String>) -> CanaryClientBuilder {
+        Self::builder_with(Credentials::ApiToken(Secret::new(api_token.into())))
+    }
+
+    /// For servers that issue user tokens: `connect` exchanges the
+    /// credentials for a token with getUserToken and sends that with every
+    /// call instead of an API token.
+    pub fn builder_for_user(username: impl Into<String>, password: impl Into<String>) -> CanaryClientBuilder {
+        Self::builder_with(Credentials::User { username: username.into(), password: Secret::new(password.into()) })
+    }
+
+    fn builder_with(credentials: Credentials) -> CanaryClientBuilder {
+        CanaryClientBuilder {
+            servers: Vec::new(),
+            api_version: DEFAULT_API_VERSION.to_string(),
+            credentials,
+            max_response_size: None,
+            compress_requests: false,
+            context_batch_size: DEFAULT_CONTEXT_BATCH_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            retries: DEFAULT_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            on_retry: OnRetry(None),
+            rate_limit: None,
+            max_requests: None,
+            audit: AuditLog::disabled(),
+            run_id: None,
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+        }
+    }
+
+    /// Base URL of the server this client talks to.
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Identifies this client's calls in the `X-Run-ID` header. Each call
+    /// also gets an `X-Correlation-ID` of the run ID and a sequence number.
+    pub fn run_id(&self) -> &str {
+        &self.transport.run_id
+    }
+
+    /// Servers that were tried before `server` and could not be reached.
+    pub fn skipped_servers(&self) -> &[String] {
+        &self.skipped_servers
+    }
+
+    pub async fn browse_tags(&self, browse: &BrowseRequest) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut payload = serde_json::to_value(browse)?;
+        self.token.authorize(&mut payload);
+        let max_size = self.max_response_size;
+        self.call("browseTags", &payload, move |body| response::parse_browse_tags(body, max_size), Vec::len).await
+    }
+
+    /// Fetches the context of `tags`, one getTagContext call per batch of
+    /// the builder's `context_batch_size` tags, with up to `concurrency`
+    /// calls in flight. Contexts come back in the order of `tags`.
+    pub async fn get_tag_context(&self, tags: &[String]) -> Result<Vec<TagContext>, Box<dyn Error>> {
+        self.get_tag_context_with_progress(tags, |_| {}).await
+    }
+
+    /// Like `get_tag_context`, calling `progress` with the number of tags
+    /// of each batch as it completes.
+    pub async fn get_tag_context_with_progress(&self, tags: &[String], progress: impl Fn(usize)) -> Result<Vec<TagContext>, Box<dyn Error>> {
+        let batches: Vec<Vec<TagContext>> = self
+            .get_tag_context_batches(tags)
+            .map(|(batch, contexts)| {
+                progress(batch.len());
+                contexts
+            })
+            .try_collect()
+            .await?;
+        Ok(batches.into_iter().flatten().collect())
+    }
+
+    /// The getTagContext calls of `get_tag_context` as a stream of each
+    /// batch of tags with its contexts, in the order of `tags`, so callers
+    /// can use a batch as soon as it arrives.
+    pub fn get_tag_context_batches<'a>(&'a self, tags: &'a [String]) -> impl Stream<Item = ContextBatch<'a>> + 'a {
+        stream::iter(tags.chunks(self.context_batch_size))
+            .map(move |batch| async move {
+                let mut payload = serde_json::json!({ "tags": batch });
+                self.token.authorize(&mut payload);
+                let max_size = self.max_response_size;
+                (batch, self.call("getTagContext", &payload, move |body| response::parse_tag_context(body, max_size), Vec::len).await)
+            })
+            .buffered(self.concurrency)
+    }
+
+    /// Reads the samples of the requested tags in the time range. When the
+    /// server stops at its size limit the read is continued until every
+    /// sample has arrived. The audit log counts samples, not tags.
+    pub async fn get_tag_data(&self, request: &TagDataRequest) -> Result<Vec<TagData>, Box<dyn Error>> {
+        let mut payload = serde_json::to_value(request)?;
+        self.token.authorize(&mut payload);
+        let mut data = self.read_tag_data_page(&payload).await?;
+        while !data.continuation().is_null() {
+            let continuation = data.continuation().clone();
+            payload["continuation"] = continuation.clone();
+            let page = self.read_tag_data_page(&payload).await?;
+            if *page.continuation() == continuation {
+                return Err(format!("getTagData returned the same continuation twice ({}); stopping instead of reading it again", continuation).into());
+            }
+            data.merge(page);
+        }
+        Ok(data.into_data())
+    }
+
+    /// Reads only the first response of a getTagData read: up to the
+    /// request's `max_size` samples per tag, with the continuation the
+    /// server sends when there are more. Useful to probe a range before
+    /// reading all of it.
+    pub async fn get_tag_data_page(&self, request: &TagDataRequest) -> Result<TagDataPage, Box<dyn Error>> {
+        let mut payload = serde_json::to_value(request)?;
+        self.token.authorize(&mut payload);
+        self.read_tag_data_page(&payload).await
+    }
+
+    async fn read_tag_data_page(&self, payload: &serde_json::Value) -> Result<TagDataPage, Box<dyn Error>> {
+        let max_size = self.max_response_size;
+        self.call("getTagData", payload, move |body| response::parse_tag_data_page(body, max_size), |page| page.data().iter().map(|tag| tag.values().len()).sum()).await
+    }
+
+    /// Asks the server for the names of the given quality codes, e.g. to
+    /// refresh a `QualityTable`.
+    pub async fn get_qualities(&self, codes: &[u32]) -> Result<BTreeMap<u32, String>, Box<dyn Error>> {
+        let mut payload = serde_json::json!({ "qualities": codes });
+        self.token.authorize(&mut payload);
+        let max_size = self.max_response_size;
+        self.call("getQualities", &payload, move |body| response::parse_qualities(body, max_size), BTreeMap::len).await
+    }
+
+    /// Opens a live data session that reports every new sample of `tags`.
+    pub async fn open_live_session(&self, tags: &[String]) -> Result<LiveSession, Box<dyn Error>> {
+        let mut payload = serde_json::json!({
+            "tags": tags,
+            "mode": "AllValues",
+            "includeQuality": true
+        });
+        self.token.authorize(&mut payload);
+        let max_size = self.max_response_size;
+        let token = self.call("getLiveDataToken", &payload, move |body| response::parse_live_data_token(body, max_size), |_| 1).await?;
+        Ok(LiveSession { token: Secret::new(token), continuation: serde_json::Value::Null })
+    }
+
+    /// Returns the samples that arrived since the previous poll of `session`.
+    pub async fn poll_live_session(&self, session: &mut LiveSession) -> Result<Vec<TagData>, Box<dyn Error>> {
+        let mut payload = serde_json::json!({
+            "liveDataToken": session.token.expose(),
+            "continuation": session.continuation
+        });
+        self.token.authorize(&mut payload);
+        let max_size = self.max_response_size;
+        let live = self.call("getLiveData", &payload, move |body| response::parse_live_data(body, max_size), |live| live.data().iter().map(|tag| tag.values().len()).sum()).await?;
+        session.continuation = live.continuation().clone();
+        Ok(live.into_data())
+    }
+
+    /// Ends a live data session on the server.
+    pub async fn close_live_session(&self, session: LiveSession) -> Result<(), Box<dyn Error>> {
+        let mut payload = serde_json::json!({ "liveDataToken": session.token.expose() });
+        self.token.authorize(&mut payload);
+        self.call("revokeLiveDataToken", &payload, |_| Ok(()), |_| 0).await
+    }
+
+    /// Revokes the user token this client logged in with and stops keeping
+    /// it alive; nothing to do for an API token, or when already closed.
+    /// Later calls fail.
+    pub async fn close(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(keep_alive) = &self.keep_alive {
+            keep_alive.abort();
+        }
+        if let Token::User(session) = &self.token {
+            if session.closed.swap(true, Ordering::Relaxed) {
+                return Ok(());
+            }
+            let token = read(&session.token).clone();
+            self.revoke_user_token(token.expose()).await?;
+        }
+        Ok(())
+    }
+
+    /// Revokes any user token, e.g. a leaked one. The call is authorized by
+    /// the token itself, not by this client's credentials.
+    pub async fn revoke_user_token(&self, token: &str) -> Result<(), Box<dyn Error>> {
+        let payload = serde_json::json!({ "userToken": token });
+        Ok(self.transport.call_once("revokeUserToken", &payload, |_| Ok(()), |_| 0).await?)
+    }
+
+    async fn log_in(&self, session: &UserSession) -> Result<(), Box<dyn Error>> {
+        self.transport.check_budget("getUserToken")?;
+        let payload = serde_json::json!({ "username": session.username, "password": session.password.expose() });
+        let max_size = self.max_response_size;
+        let token = self.transport.call_once("getUserToken", &payload, move |body| response::parse_user_token(body, max_size), |_| 1).await?;
+        *session.token.write().unwrap_or_else(PoisonError::into_inner) = Secret::new(token);
+        Ok(())
+    }
+
+    /// Sends one API call; see `Transport::call_once`. A call the server rejects as
+    /// unauthorized while using a user token logs in again and is retried
+    /// once, so an expired token does not end a long run. A transient
+    /// failure is retried up to `retries` times with exponential backoff.
+    async fn call<T, F>(&self, endpoint: &'static str, payload: &serde_json::Value, parse: F, rows: fn(&T) -> usize) -> Result<T, Box<dyn Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce(Box<dyn Read + Send>) -> Result<T, ResponseError> + Clone + Send + 'static,
+    {
+        let mut payload = Cow::Borrowed(payload);
+        let mut logged_in_again = false;
+        let mut attempt = 0;
+        loop {
+            self.transport.check_budget(endpoint)?;
+            let error = match self.transport.call_once(endpoint, &payload, parse.clone(), rows).await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            match &self.token {
+                Token::User(session) if error.is_unauthorized() && !logged_in_again && !session.closed.load(Ordering::Relaxed) => {
+                    self.log_in(session).await?;
+                    self.token.authorize(payload.to_mut());
+                    logged_in_again = true;
+                    continue;
+                }
+                _ if attempt < self.retries && error.is_transient() => {}
+                _ => return Err(error.into()),
+            }
+            attempt += 1;
+            let delay = self.retry_delay(attempt);
+            if let Some(on_retry) = &self.on_retry.0 {
+                on_retry(endpoint, &error, attempt, delay);
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Delay before retry `attempt`: `retry_delay` doubled for each earlier
+    /// retry, capped at 30 s, of which a random 0 to 50% is taken off so
+    /// parallel clients do not retry in step.
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        let delay = self.retry_delay.saturating_mul(2u32.saturating_pow(attempt - 1)).min(MAX_RETRY_DELAY);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// Keeps an idle user token from expiring by calling keepAlive whenever
+    /// no other call was made for `interval`. Keep-alives are audited like
+    /// any other call; a failed one is left to the next call, which logs in
+    /// again.
+    fn spawn_keep_alive(&self, session: Arc<UserSession>, interval: Duration) -> AbortHandle {
+        let transport = self.transport.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let idle = lock(&session.last_used).elapsed();
+                if idle < interval || transport.check_budget("keepAlive").is_err() {
+                    continue;
+                }
+                let payload = serde_json::json!({ "userToken": read(&session.token).expose() });
+                let _ = transport.call_once("keepAlive", &payload, |_| Ok(()), |_| 0).await;
+            }
+        })
+        .abort_handle()
+    }
+}
+
+/// What every API call goes through: the HTTP client, the rate limit, the
+/// audit log and the sequence behind correlation IDs. Shared with the
+/// keep-alive task so its calls are instrumented like any other.
+#[derive(Debug)]
+struct Transport {
+    http: Client,
+    url: String,
+    run_id: String,
+    compress_requests: bool,
+    rate_limiter: Option<RateLimiter>,
+    audit: AuditLog,
+    calls: AtomicU64,
+    max_requests: Option<u64>,
+}
+
+impl Transport {
+    /// Fails once `max_requests` calls were made. Revoking a token is always
+    /// allowed, so no session is left open.
+    fn check_budget(&self, endpoint: &str) -> Result<(), Box<dyn Error>> {
+        match self.max_requests {
+            Some(max) if self.calls.load(Ordering::Relaxed) >= max && !endpoint.starts_with("revoke") => {
+                Err(format!("{} not sent: the limit of {} API calls for this client is reached", endpoint, max).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends one API call under a fresh correlation ID, records it in the
+    /// audit log and tags a failure with the ID so it can be matched to the
+    /// server's request log.
+    async fn call_once<T, F>(&self, endpoint: &'static str, payload: &serde_json::Value, parse: F, rows: fn(&T) -> usize) -> Result<T, CallError>
+    where
+        T: Send + 'static,
+        F: FnOnce(Box<dyn Read + Send>) -> Result<T, ResponseError> + Send + 'static,
+    {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        let correlation_id = format!("{}-{}", self.run_id, self.calls.fetch_add(1, Ordering::Relaxed) + 1);
+        let started = Instant::now();
+        let mut status = None;
+        let received = Arc::new(AtomicU64::new(0));
+        tracing::trace!(endpoint, correlation_id, payload = %audit::redact(payload), "API request");
+        let result = self.post(endpoint, payload, &correlation_id, &mut status, received.clone(), parse).await;
+        tracing::debug!(
+            endpoint,
+            url = format!("{}/{}", self.url, endpoint),
+            correlation_id,
+            status,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            bytes = received.load(Ordering::Relaxed),
+            "API call"
+        );
+        let recorded = self.audit.record(endpoint, &correlation_id, payload, started.elapsed(), status, result.as_ref().map(rows).map_err(|e| e.to_string()));
+
+        match (result, recorded) {
+            (Err(source), _) | (Ok(_), Err(source)) => Err(CallError::new(endpoint, correlation_id, source)),
+            (Ok(value), Ok(())) => Ok(value),
+        }
+    }
+
+    async fn post<T, F>(&self, endpoint: &'static str, payload: &serde_json::Value, correlation_id: &str, status: &mut Option<u16>, received: Arc<AtomicU64>, parse: F) -> Result<T, Box<dyn Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce(Box<dyn Read + Send>) -> Result<T, ResponseError> + Send + 'static,
+    {
+        let request = self.http.post(format!("{}/{}", self.url, endpoint))
+            .header(CONTENT_TYPE, "application/json")
+            .header(RUN_ID_HEADER, &self.run_id)
+            .header(CORRELATION_ID_HEADER, correlation_id);
+        let request = if self.compress_requests {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            serde_json::to_writer(&mut encoder, payload)?;
+            request.header(CONTENT_ENCODING, "gzip").body(encoder.finish()?)
+        } else {
+            request.body(serde_json::to_vec(payload)?)
+        };
+        let mut response = request.send().await?;
+        let code = response.status().as_u16();
+        *status = Some(code);
+
+        if !response.status().is_success() {
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                body.extend_from_slice(&chunk);
+                if body.len() >= ERROR_BODY_LIMIT {
+                    break;
+                }
+            }
+            response::check_status(endpoint, code, &body)?;
+        }
+
+        let stream = StreamReader::new(response.bytes_stream().map_ok(move |chunk| {
+            received.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            chunk
+        }).map_err(io::Error::other));
+        let body: Box<dyn Read + Send> = Box::new(SyncIoBridge::new(stream));
+        Ok(tokio::task::spawn_blocking(move || parse(body)).await??)
+    }
+}
+
+#[derive(Debug)]
+pub struct CanaryClientBuilder {
+    servers: Vec<String>,
+    api_version: String,
+    credentials: Credentials,
+    max_response_size: Option<u64>,
+    compress_requests: bool,
+    context_batch_size: usize,
+    concurrency: usize,
+    retries: u32,
+    retry_delay: Duration,
+    on_retry: OnRetry,
+    rate_limit: Option<f64>,
+    max_requests: Option<u64>,
+    audit: AuditLog,
+    run_id: Option<String>,
+    keep_alive_interval: Duration,
+}
+
+impl CanaryClientBuilder {
+    /// Base URL of a Canary server. Give several to fail over in order.
+    pub fn server(mut self, url: impl Into<String>) -> Self {
+        self.servers.push(url.into());
+        self
+    }
+
+    /// API path below the server URL. Defaults to `api/v2`.
+    pub fn api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.api_version = api_version.into();
+        self
+    }
+
+    /// Fail a call whose response body exceeds this many bytes.
+    pub fn max_response_size(mut self, limit: Option<u64>) -> Self {
+        self.max_response_size = limit;
+        self
+    }
+
+    /// Gzip request bodies. The server must accept `Content-Encoding: gzip`.
+    pub fn compress_requests(mut self, compress: bool) -> Self {
+        self.compress_requests = compress;
+        self
+    }
+
+    /// Most tags sent in one getTagContext call; larger lists are split.
+    /// Defaults to 500.
+    ///
+    /// # Panics
+    ///
+    /// If `size` is zero.
+    pub fn context_batch_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "context batch size must be positive");
+        self.context_batch_size = size;
+        self
+    }
+
+    /// Most getTagContext batches requested at once. Defaults to 4.
+    ///
+    /// # Panics
+    ///
+    /// If `concurrency` is zero.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        assert!(concurrency > 0, "concurrency must be positive");
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// How often a call that failed transiently is retried: on a 408, 429,
+    /// 502, 503 or 504 response, or when the connection failed or timed
+    /// out. Defaults to 3; 0 turns retries off.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled for each further one and
+    /// jittered. Defaults to 500 ms.
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Called before each retry with the endpoint, the error, the retry
+    /// number (from 1) and the delay before it.
+    pub fn on_retry(mut self, callback: impl Fn(&str, &CallError, u32, Duration) + Send + Sync + 'static) -> Self {
+        self.on_retry = OnRetry(Some(Arc::new(callback)));
+        self
+    }
+
+    /// Most API calls per second, shared by all concurrent calls and
+    /// retries of the client. Calls are spaced evenly rather than sent in
+    /// bursts. Unlimited by default.
+    ///
+    /// # Panics
+    ///
+    /// If the limit is not a positive number.
+    pub fn rate_limit(mut self, calls_per_second: Option<f64>) -> Self {
+        assert!(calls_per_second.is_none_or(|rate| rate > 0.0 && rate.is_finite()), "rate limit must be positive");
+        self.rate_limit = calls_per_second;
+        self
+    }
+
+    /// Most API calls the client makes, counting retries, logins and
+    /// keep-alives. A call past the limit fails without being sent, except
+    /// one that revokes a token, so no session is left open; keep-alives
+    /// past it are skipped. Unlimited by default.
+    pub fn max_requests(mut self, max: Option<u64>) -> Self {
+        self.max_requests = max;
+        self
+    }
+
+    /// Record every API call to this log.
+    pub fn audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Run ID sent with every call. Defaults to a random UUID.
+    pub fn run_id(mut self, run_id: impl Into<String>) -> Self {
+        self.run_id = Some(run_id.into());
+        self
+    }
+
+    /// How long a user token may go unused before the client calls
+    /// keepAlive for it. Defaults to a minute.
+    pub fn keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = interval;
+        self
+    }
+
+    /// Picks the first server that accepts a connection. Any HTTP response
+    /// counts as reachable; only connection failures and timeouts fail over,
+    /// and the last server is used without probing.
+    pub async fn connect(self) -> Result<CanaryClient, Box<dyn Error>> {
+        let http = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()?;
+        let Some((last, candidates)) = self.servers.split_last() else {
+            return Err("no Canary server given".into());
+        };
+
+        let mut skipped_servers = Vec::new();
+        let mut server = last;
+        for candidate in candidates {
+            match http.get(candidate.as_str()).send().await {
+                Ok(_) => {
+                    server = candidate;
+                    break;
+                }
+                Err(e) if e.is_connect() || e.is_timeout() => skipped_servers.push(candidate.clone()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let token = match self.credentials {
+            Credentials::ApiToken(token) => Token::Api(token),
+            Credentials::User { username, password } => Token::User(Arc::new(UserSession {
+                username,
+                password,
+                token: RwLock::new(Secret::new(String::new())),
+                last_used: Mutex::new(Instant::now()),
+                closed: AtomicBool::new(false),
+            })),
+        };
+        let transport = Transport {
+            http,
+            url: format!("{}/{}", server, self.api_version),
+            run_id: self.run_id.unwrap_or_else(|| Uuid::new_v4().to_string()),
+            compress_requests: self.compress_requests,
+            rate_limiter: self.rate_limit.map(RateLimiter::new),
+            audit: self.audit,
+            calls: AtomicU64::new(0),
+            max_requests: self.max_requests,
+        };
+        let mut client = CanaryClient {
+            transport: Arc::new(transport),
+            server: server.clone(),
+            token,
+            max_response_size: self.max_response_size,
+            context_batch_size: self.context_batch_size,
+            concurrency: self.concurrency,
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+            on_retry: self.on_retry,
+            skipped_servers,
+            keep_alive: None,
+        };
+        if let Token::User(session) = &client.token {
+            let session = session.clone();
+            client.log_in(&session).await?;
+            client.keep_alive = Some(client.spawn_keep_alive(session, self.keep_alive_interval));
+        }
+        Ok(client)
+    }
+}
+
+/// Token bucket holding a single token: each call takes the next free
+/// slot, one `interval` after the one before, and waits for it.
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(calls_per_second: f64) -> Self {
+        RateLimiter { interval: Duration::from_secs_f64(1.0 / calls_per_second), next_slot: Mutex::new(Instant::now()) }
+    }
+
+    async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = lock(&self.next_slot);
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+/// A batch of tags with their contexts or the error that ended its call.
+type ContextBatch<'a> = (&'a [String], Result<Vec<TagContext>, Box<dyn Error>>);
+
+type RetryCallback = dyn Fn(&str, &CallError, u32, Duration) + Send + Sync;
+
+struct OnRetry(Option<Arc<RetryCallback>>);
+
+impl fmt::Debug for OnRetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Some(..)" } else { "None" })
+    }
+}
+
+#[derive(Debug)]
+enum Credentials {
+    ApiToken(Secret<String>),
+    User { username: String, password: Secret<String> },
+}
+
+/// The token sent with every call, under the key its kind uses.
+#[derive(Debug)]
+enum Token {
+    Api(Secret<String>),
+    User(Arc<UserSession>),
+}
+
+/// A user token from getUserToken and what is needed to replace it.
+#[derive(Debug)]
+struct UserSession {
+    username: String,
+    password: Secret<String>,
+    token: RwLock<Secret<String>>,
+    last_used: Mutex<Instant>,
+    /// Set by `close`; a closed session is not logged in again.
+    closed: AtomicBool,
+}
+
+impl Token {
+    fn authorize(&self, payload: &mut serde_json::Value) {
+        match self {
+            Token::Api(token) => payload["apiToken"] = token.expose().as_str().into(),
+            Token::User(session) => {
+                *lock(&session.last_used) = Instant::now();
+                payload["userToken"] = read(&session.token).expose().as_str().into();
+            }
+        }
+    }
+}
+
+/// Locks `mutex`, going on with its value if another thread panicked while
+/// holding it: every value behind these locks is replaced whole, so it is
+/// never left half-written.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Like `lock`, for reading an `RwLock`.
+fn read<T>(rwlock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    rwlock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Drop for CanaryClient {
+    fn drop(&mut self) {
+        if let Some(keep_alive) = &self.keep_alive {
+            keep_alive.abort();
+        }
+    }
+}
+
+/// A live data session opened by `CanaryClient::open_live_session`. The
+/// session token is a credential and is never printed.
+#[derive(Debug)]
+pub struct LiveSession {
+    token: Secret<String>,
+    continuation: serde_json::Value,
+}
+
+/// A failed API call, labelled with the correlation ID it was sent with.
+#[derive(Debug)]
+pub struct CallError {
+    endpoint: &'static str,
+    correlation_id: String,
+    kind: CallErrorKind,
+    source: Box<dyn Error>,
+}
+
+/// What kind of failure a `CallError` is, for callers that react to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CallErrorKind {
+    /// The request could not be sent or the connection broke.
+    Unreachable,
+    /// The server did not answer in time.
+    Timeout,
+    /// The server answered with this non-success HTTP status.
+    Status(u16),
+    /// The response was malformed, over the size limit or cut off.
+    Unreadable,
+    /// Anything else, e.g. the audit log could not be written.
+    Other,
+}
+
+impl CallError {
+    fn new(endpoint: &'static str, correlation_id: String, source: Box<dyn Error>) -> Self {
+        let kind = match (source.downcast_ref::<ResponseError>(), source.downcast_ref::<reqwest::Error>()) {
+            (Some(ResponseError::Status { status, .. }), _) => CallErrorKind::Status(*status),
+            (Some(_), _) => CallErrorKind::Unreadable,
+            (None, Some(e)) if e.is_decode() || e.is_body() => CallErrorKind::Unreadable,
+            (None, Some(e)) if e.is_timeout() => CallErrorKind::Timeout,
+            (None, Some(_)) => CallErrorKind::Unreachable,
+            (None, None) => CallErrorKind::Other,
+        };
+        CallError { endpoint, correlation_id, kind, source }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        self.endpoint
+    }
+
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+
+    pub fn kind(&self) -> CallErrorKind {
+        self.kind
+    }
+
+    fn is_unauthorized(&self) -> bool {
+        matches!(self.kind, CallErrorKind::Status(401 | 403))
+    }
+
+    /// Whether the same call may well succeed if sent again.
+    fn is_transient(&self) -> bool {
+        match self.kind {
+            CallErrorKind::Status(status) => matches!(status, 408 | 429 | 502 | 503 | 504),
+            CallErrorKind::Timeout => true,
+            CallErrorKind::Unreachable => self.source.downcast_ref::<reqwest::Error>().is_some_and(reqwest::Error::is_connect),
+            CallErrorKind::Unreadable | CallErrorKind::Other => false,
+        }
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed (correlation ID {})", self.endpoint, self.correlation_id)
+    }
+}
+
+impl Error for CallError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
@@ -0,0 +1,73 @@
+use canary_context::response::TagData;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+/// Sample count and value hash of one tag in one window.
+#[derive(Default)]
+struct Window {
+    samples: usize,
+    hash: Sha256,
+}
+
+/// A window of a tag whose samples differ between the two servers.
+pub struct Mismatch {
+    pub tag_name: String,
+    pub start: DateTime<Utc>,
+    pub first: usize,
+    pub second: usize,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.start.to_rfc3339_opts(SecondsFormat::Secs, true);
+        if self.first == self.second {
+            write!(f, "{} {}: {} samples on both, values differ", self.tag_name, start, self.first)
+        } else {
+            write!(f, "{} {}: {} samples on the first server, {} on the second", self.tag_name, start, self.first, self.second)
+        }
+    }
+}
+
+/// Buckets the samples of both reads into windows of `window_secs` seconds
+/// aligned to the Unix epoch and compares each window's sample count and
+/// hash. Timestamps are hashed as UTC instants and values as JSON, so two
+/// servers that render the same sample with different UTC offsets agree.
+/// Returns the number of windows compared and the ones that differ, in tag
+/// and time order.
+pub fn compare(first: &[TagData], second: &[TagData], window_secs: u64) -> Result<(usize, Vec<Mismatch>), Box<dyn Error>> {
+    let mut first = windows(first, window_secs)?;
+    let mut second = windows(second, window_secs)?;
+    let mut keys: Vec<(String, i64)> = first.keys().chain(second.keys()).cloned().collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut mismatches = Vec::new();
+    for key in &keys {
+        let (a, b) = (first.remove(key).unwrap_or_default(), second.remove(key).unwrap_or_default());
+        if a.samples != b.samples || a.hash.finalize() != b.hash.finalize() {
+            let start = DateTime::from_timestamp(key.1, 0).ok_or("window start out of range")?;
+            mismatches.push(Mismatch { tag_name: key.0.clone(), start, first: a.samples, second: b.samples });
+        }
+    }
+    Ok((keys.len(), mismatches))
+}
+
+fn windows(data: &[TagData], window_secs: u64) -> Result<BTreeMap<(String, i64), Window>, Box<dyn Error>> {
+    let window_secs = i64::try_from(window_secs).map_err(|_| "--window is too large")?;
+    let mut windows: BTreeMap<(String, i64), Window> = BTreeMap::new();
+    for tag in data {
+        for sample in tag.values() {
+            let time = DateTime::parse_from_rfc3339(sample.timestamp()).map_err(|e| format!("cannot read timestamp {:?} of {}: {}", sample.timestamp(), tag.tag_name(), e))?;
+            let nanos = time.timestamp_nanos_opt().ok_or_else(|| format!("timestamp {:?} of {} is out of range", sample.timestamp(), tag.tag_name()))?;
+            let window = windows.entry((tag.tag_name().to_string(), time.timestamp().div_euclid(window_secs) * window_secs)).or_default();
+            window.samples += 1;
+            window.hash.update(nanos.to_le_bytes());
+            window.hash.update(serde_json::to_vec(sample.value())?);
+            window.hash.update(sample.quality().map_or(-1, i64::from).to_le_bytes());
+        }
+    }
+    Ok(windows)
+}
@@ -0,0 +1,153 @@
+use clap::Command;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// The config file: named connection profiles, and which one to use when
+/// `--profile` is not given.
+///
+/// ```toml
+/// default_profile = "dev"
+///
+/// [profiles.dev]
+/// canary = "https://canary-dev:55236"
+/// api_token = "..."
+///
+/// [profiles.prod]
+/// canary = ["https://canary-a:55236", "https://canary-b:55236"]
+/// api_token = "..."
+/// application = "Inventory"
+/// timezone = "Eastern Standard Time"
+///
+/// [profiles.prod.export]
+/// output_format = "csv"
+/// max_tags = 50000
+///
+/// [profiles.prod.data]
+/// batch_size = 1000
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// Values a profile can set. Each one stands in for the flag of the same
+/// name and is overridden by that flag on the command line. A table named
+/// after a subcommand holds defaults for that subcommand's flags only.
+#[derive(Debug, Default, Deserialize)]
+pub struct Profile {
+    canary: Option<Servers>,
+    api_version: Option<String>,
+    api_token: Option<String>,
+    application: Option<String>,
+    timezone: Option<String>,
+    #[serde(flatten)]
+    subcommands: HashMap<String, toml::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Servers {
+    One(String),
+    Failover(Vec<String>),
+}
+
+/// `~/.config/canary-context/config.toml`, or the platform's equivalent.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("canary-context").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the config file. A missing file is only an error when the user
+    /// named it explicitly.
+    pub fn load(path: &Path, explicit: bool) -> Result<Self, Box<dyn Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("invalid config file {}: {}", path.display(), e).into()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => Ok(Config::default()),
+            Err(e) => Err(format!("cannot read config file {}: {}", path.display(), e).into()),
+        }
+    }
+
+    /// The profile named on the command line, else the file's default
+    /// profile, else none.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>, Box<dyn Error>> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(Some(profile)),
+            None => {
+                let mut known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                known.sort_unstable();
+                Err(format!("unknown profile '{}'; the config file defines: {}", name, known.join(", ")).into())
+            }
+        }
+    }
+}
+
+impl Profile {
+    /// Makes the profile's values the defaults of the matching flags, on the
+    /// root command and every subcommand that has them, followed by the
+    /// defaults the profile sets for `subcommand`, the one being run.
+    pub fn apply(&self, command: Command, subcommand: Option<&str>) -> Result<Command, Box<dyn Error>> {
+        let mut defaults: Vec<(&str, Vec<String>)> = Vec::new();
+        match &self.canary {
+            Some(Servers::One(server)) => defaults.push(("canary", vec![server.clone()])),
+            Some(Servers::Failover(servers)) => defaults.push(("canary", servers.clone())),
+            None => {}
+        }
+        let single = [("api_version", &self.api_version), ("api_token", &self.api_token), ("application", &self.application), ("timezone", &self.timezone)];
+        defaults.extend(single.into_iter().filter_map(|(id, value)| value.as_ref().map(|value| (id, vec![value.clone()]))));
+
+        let mut names: Vec<&String> = self.subcommands.keys().collect();
+        names.sort();
+        let mut flags = Vec::new();
+        for name in names {
+            let Some(table) = self.subcommands[name].as_table() else {
+                return Err(format!("unknown profile setting '{}'; subcommand defaults go in a [profiles.<profile>.<subcommand>] table", name).into());
+            };
+            let Some(target) = command.find_subcommand(name) else {
+                return Err(format!("unknown subcommand [{}] in profile; expected one of: {}", name, command.get_subcommands().map(Command::get_name).collect::<Vec<_>>().join(", ")).into());
+            };
+            for (flag, value) in table {
+                let id = flag.replace('-', "_");
+                if !target.get_arguments().chain(command.get_arguments()).any(|arg| arg.get_id() == id.as_str()) {
+                    return Err(format!("unknown flag '{}' in the profile's [{}] table", flag, name).into());
+                }
+                if subcommand == Some(name.as_str()) {
+                    flags.push((id, flag_values(value).ok_or_else(|| format!("'{}' in the profile's [{}] table must be a string, number, boolean or a list of them", flag, name))?));
+                }
+            }
+        }
+        defaults.extend(flags.iter().map(|(id, values)| (id.as_str(), values.clone())));
+        Ok(with_defaults(command, &defaults))
+    }
+}
+
+/// A subcommand default as the command-line value(s) it stands for.
+fn flag_values(value: &toml::Value) -> Option<Vec<String>> {
+    match value {
+        toml::Value::String(text) => Some(vec![text.clone()]),
+        toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) | toml::Value::Datetime(_) => Some(vec![value.to_string()]),
+        toml::Value::Array(values) => values.iter().map(|value| flag_values(value).filter(|values| values.len() == 1).map(|mut values| values.remove(0))).collect(),
+        toml::Value::Table(_) => None,
+    }
+}
+
+fn with_defaults(mut command: Command, defaults: &[(&str, Vec<String>)]) -> Command {
+    for (id, values) in defaults {
+        if command.get_arguments().any(|arg| arg.get_id() == id) {
+            // A default satisfies a required flag.
+            command = command.mut_arg(*id, |arg| arg.default_values(values.clone()).required(false));
+        }
+    }
+    let names: Vec<String> = command.get_subcommands().map(|subcommand| subcommand.get_name().to_string()).collect();
+    for name in names {
+        command = command.mut_subcommand(name, |subcommand| with_defaults(subcommand, defaults));
+    }
+    command
+}
@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+/// Character encodings CSV and TXT output can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    /// Characters the code page has no byte for are written as `?`.
+    Windows1252,
+}
+
+/// Windows-1252 bytes 0x80 to 0x9F; the five the code page leaves undefined
+/// map to the C1 control with the same number, as browsers do.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+impl Encoding {
+    pub const NAMES: [&'static str; 3] = ["utf-8", "utf-16le", "windows-1252"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "utf-8" => Some(Encoding::Utf8),
+            "utf-16le" => Some(Encoding::Utf16Le),
+            "windows-1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// Whether the encoding has a byte order mark.
+    pub fn has_bom(self) -> bool {
+        self != Encoding::Windows1252
+    }
+
+    fn encode(self, text: &str, out: &mut Vec<u8>) {
+        match self {
+            Encoding::Utf8 => out.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => out.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Encoding::Windows1252 => out.extend(text.chars().map(|c| match c as u32 {
+                0..=0x7F | 0xA0..=0xFF => c as u8,
+                _ => WINDOWS_1252_HIGH.iter().position(|high| *high == c).map_or(b'?', |i| 0x80 + i as u8),
+            })),
+        }
+    }
+}
+
+/// Re-encodes the UTF-8 written to it before passing it on. A character
+/// split across two writes is held back until it is complete.
+pub struct EncodedWriter<W: Write> {
+    inner: W,
+    encoding: Encoding,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> EncodedWriter<W> {
+    pub fn new(inner: W, encoding: Encoding) -> Self {
+        EncodedWriter { inner, encoding, pending: Vec::new() }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: Write> Write for EncodedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding == Encoding::Utf8 {
+            return self.inner.write(buf);
+        }
+        self.pending.extend_from_slice(buf);
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let mut encoded = Vec::with_capacity(complete * 2);
+        self.encoding.encode(std::str::from_utf8(&self.pending[..complete]).unwrap(), &mut encoded);
+        self.inner.write_all(&encoded)?;
+        self.pending.drain(..complete);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,96 @@
+use canary_context::response::TagDataPage;
+use chrono::{DateTime, Duration as TimeDelta, Utc};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Samples per tag read to size a `data` export when --max_size is not set.
+pub const PROBE_SIZE: u32 = 1000;
+
+/// The projected size of a `data` read.
+pub struct Estimate {
+    pub samples: u64,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "about {} samples, {:.1} MB, {}s to read", self.samples, self.bytes as f64 / 1_048_576.0, self.duration.as_secs().max(1))
+    }
+}
+
+/// Projects the whole read from a first page of at most `probe_size`
+/// samples per tag. A tag with fewer samples than that was read in full,
+/// unless the page has a continuation: then the server capped the page
+/// below `probe_size` and every tag may have more. For truncated tags the
+/// sample rate between the first and last probed sample is carried over
+/// the whole range. Size and read time are scaled from the probe by sample
+/// count.
+pub fn estimate(page: &TagDataPage, probe_size: u32, range: TimeDelta, probe_bytes: u64, probe_elapsed: Duration) -> Result<Estimate, Box<dyn Error>> {
+    let truncated = !page.continuation().is_null();
+    let mut samples = 0u64;
+    for tag in page.data() {
+        let values = tag.values();
+        let probed = values.len() as u64;
+        samples += match (values.first(), values.last()) {
+            (Some(first), Some(last)) if truncated || probed >= u64::from(probe_size) => {
+                let span = time(last.timestamp())? - time(first.timestamp())?;
+                if span > TimeDelta::zero() {
+                    ((probed - 1) as f64 * range.num_milliseconds() as f64 / span.num_milliseconds() as f64).max(probed as f64) as u64
+                } else {
+                    probed
+                }
+            }
+            _ => probed,
+        };
+    }
+    let probed: u64 = page.data().iter().map(|tag| tag.values().len() as u64).sum();
+    let scale = if probed == 0 { 0.0 } else { samples as f64 / probed as f64 };
+    Ok(Estimate { samples, bytes: (probe_bytes as f64 * scale) as u64, duration: probe_elapsed.mul_f64(scale) })
+}
+
+/// The length of a `--start_time`/`--end_time` range. Only timestamps and
+/// times relative to now (`Now`, `Now-7Days`, `Now+1Hour`) can be resolved
+/// here; months count as 30 days and years as 365.
+pub fn range(start: &str, end: &str, now: DateTime<Utc>) -> Result<TimeDelta, Box<dyn Error>> {
+    let range = resolve(end, now)? - resolve(start, now)?;
+    if range <= TimeDelta::zero() {
+        return Err(format!("--end_time {} is not after --start_time {}", end, start).into());
+    }
+    Ok(range)
+}
+
+fn resolve(text: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.to_utc());
+    }
+    let unsupported = || format!("--estimate cannot resolve the time {:?}; give a timestamp or a time relative to Now, such as Now-1Day", text);
+    let offset = text.strip_prefix("Now").ok_or_else(unsupported)?.trim();
+    if offset.is_empty() {
+        return Ok(now);
+    }
+    let (sign, offset) = match offset.split_at(1) {
+        ("-", rest) => (-1, rest),
+        ("+", rest) => (1, rest),
+        _ => return Err(unsupported().into()),
+    };
+    let digits = offset.find(|c: char| !c.is_ascii_digit()).unwrap_or(offset.len());
+    let count: i64 = offset[..digits].parse().map_err(|_| unsupported())?;
+    let unit = offset[digits..].trim().to_ascii_lowercase();
+    let unit = match unit.strip_suffix('s').unwrap_or(&unit) {
+        "second" => TimeDelta::seconds(1),
+        "minute" => TimeDelta::minutes(1),
+        "hour" => TimeDelta::hours(1),
+        "day" => TimeDelta::days(1),
+        "week" => TimeDelta::weeks(1),
+        "month" => TimeDelta::days(30),
+        "year" => TimeDelta::days(365),
+        _ => return Err(unsupported().into()),
+    };
+    Ok(now + unit * i32::try_from(sign * count).map_err(|_| unsupported())?)
+}
+
+fn time(text: &str) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    Ok(DateTime::parse_from_rfc3339(text).map_err(|e| format!("cannot read timestamp {:?}: {}", text, e))?.to_utc())
+}
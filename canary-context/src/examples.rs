@@ -0,0 +1,81 @@
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use std::error::Error;
+
+const PLACEHOLDER_SERVER: &str = "https://canary:55236";
+
+/// Example invocations, as the arguments after the connection flags.
+const EXAMPLES: &[(&str, &[&str])] = &[
+    ("List every tag name the server browses to", &["browse"]),
+    ("List the tags of two historians, retrying if the server is still starting", &["browse", "--historian", "North", "--historian", "South", "--retry_on_empty", "3"]),
+    ("Find typo'd duplicate tags, such as Temperature next to Temprature", &["near_duplicates"]),
+    ("Write every tag name to a file for other tools, without fetching context", &["tags", "--output_format", "txt", "--output_file", "tags.txt"]),
+    ("Print the context of two tags as JSON", &["context", "Plant1.Line1.Temp", "Plant1.Line1.Pressure"]),
+    ("Find the current tag of a historian item ID from an old export", &["lookup", "hist-1234"]),
+    ("Export the context of every tag to CSV", &["export", "--output_format", "csv", "--output_file", "tags.csv"]),
+    ("Export the context of every tag to an Excel workbook", &["export", "--output_format", "xlsx", "--output_file", "tags.xlsx"]),
+    ("Export an Excel workbook for a German plant, with timestamps as dates in day.month.year order", &["export", "--output_format", "xlsx", "--output_file", "tags.xlsx", "--locale", "de-DE"]),
+    ("Stream one JSON object per tag to stdout for jq or log shippers", &["export", "--output_format", "ndjson", "--output_file", "-"]),
+    ("Export the context of a whole historian to a gzip-compressed CSV", &["export", "--output_format", "csv", "--output_file", "tags.csv.gz"]),
+    ("Export to an Arrow IPC (Feather) file for Polars or pandas", &["export", "--output_format", "arrow", "--output_file", "tags.arrow"]),
+    ("Keep a queryable SQLite copy of the tag context, refreshed in place on every run", &["export", "--output_format", "sqlite", "--output_file", "tags.db"]),
+    ("Export the context of a known tag list piped from another system, without browsing", &["export", "--output_format", "json", "--output_file", "tags.json", "--tags_file", "-"]),
+    ("Export only the temperature tags of Plant1, filtering names before fetching context", &["export", "--output_format", "csv", "--output_file", "plant1.csv", "--glob", "Plant1/**/Temp*"]),
+    ("Export to one-line JSON and commit it when the inventory changed", &["export", "--output_format", "json", "--output_file", "inventory/tags.json", "--json_compact", "--git_commit", "inventory"]),
+    ("Export to TXT in the layout of a template file using {tag_name}, {latest_time_stamp}, ... placeholders", &["export", "--output_format", "txt", "--output_file", "tags.txt", "--txt_template", "record.tmpl"]),
+    ("Export and load the rows into ClickHouse", &["export", "--output_format", "csv", "--output_file", "tags.csv", "--clickhouse_url", "http://clickhouse:8123", "--clickhouse_table", "canary.tags"]),
+    ("Write the last day of raw samples of a tag to CSV", &["data", "Plant1.Line1.Temp", "--start_time", "Now-1Day", "--output_format", "csv", "--output_file", "history.csv"]),
+    ("Read yesterday's samples of a meter plus the bounding samples just outside the day", &["data", "Plant1.Meter1.kWh", "--start_time", "Now-1Day", "--bounds", "outside", "--output_format", "csv", "--output_file", "meter.csv"]),
+    ("Size a year of raw samples from a first page and confirm before reading it all", &["data", "Plant1.Line1.Temp", "--start_time", "Now-1Year", "--estimate", "--output_format", "csv", "--output_file", "year.csv"]),
+    ("Write hourly averages of a tag for the last week", &["data", "Plant1.Line1.Temp", "--start_time", "Now-7Days", "--aggregate", "TimeAverage2", "--aggregate_interval", "1:00:00", "--output_format", "csv", "--output_file", "hourly.csv"]),
+    ("Check that a migrated server holds the same samples of a tag as the old one, hour by hour", &["compare_data", "Plant1.Line1.Temp", "--other_canary", "https://new-canary:55236", "--start_time", "Now-30Days"]),
+    ("Follow new samples of two tags until Ctrl+C", &["live", "Plant1.Line1.Temp", "Plant1.Line1.Pressure"]),
+    ("Revoke a leaked user token, reading it from stdin", &["revoke_token", "-"]),
+    ("Approve the current tag inventory", &["baseline", "write", "baseline.json"]),
+    ("Fail if the tag inventory differs from the approved one", &["baseline", "check", "baseline.json"]),
+];
+
+/// Prints every example with the server of the current profile (or a
+/// placeholder). The token is only shown as a placeholder, and left out when
+/// a profile or the environment supplies it. Each example is parsed with
+/// `cli` first, so an example that no longer matches the flags fails here
+/// instead of misleading whoever copies it.
+pub fn print(cli: impl Fn() -> Command, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let servers: Vec<&str> = matches.get_many::<String>("canary").map(|servers| servers.map(String::as_str).collect()).unwrap_or_default();
+    let mut connection: Vec<String> = matches.get_one::<String>("profile").map(|profile| vec!["--profile".to_string(), profile.clone()]).unwrap_or_default();
+    connection.extend(["--canary".to_string(), if servers.is_empty() { PLACEHOLDER_SERVER.to_string() } else { servers.join(",") }]);
+    if !matches!(matches.value_source("api_token"), Some(ValueSource::DefaultValue | ValueSource::EnvVariable)) {
+        connection.extend(["--api_token".to_string(), "<token>".to_string()]);
+    }
+
+    let mut examples = Vec::with_capacity(EXAMPLES.len());
+    for (description, args) in EXAMPLES {
+        let argv: Vec<String> = std::iter::once("canary-context".to_string()).chain(connection.iter().cloned()).chain(args.iter().map(|arg| arg.to_string())).collect();
+        cli().try_get_matches_from(&argv).map_err(|e| format!("example '{}' no longer parses: {}", description, e.render()))?;
+        examples.push((description, argv));
+    }
+    for (description, argv) in examples {
+        println!("# {}", description);
+        println!("{}", argv.iter().map(|arg| quote(arg)).collect::<Vec<_>>().join(" "));
+        println!();
+    }
+    Ok(())
+}
+
+/// Parses every example with placeholder connection flags and returns how
+/// many there are.
+pub fn check(cli: impl Fn() -> Command) -> Result<usize, Box<dyn Error>> {
+    for (description, args) in EXAMPLES {
+        let argv = ["canary-context", "--canary", PLACEHOLDER_SERVER, "--api_token", "<token>"].into_iter().chain(args.iter().copied());
+        cli().try_get_matches_from(argv).map_err(|e| format!("example '{}' no longer parses: {}", description, e.render()))?;
+    }
+    Ok(EXAMPLES.len())
+}
+
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:,=".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
@@ -0,0 +1,123 @@
+use canary_context::{CallError, CallErrorKind};
+use std::error::Error;
+use std::process::ExitCode;
+use thiserror::Error;
+
+/// Exit codes, listed in `--help`.
+pub const CODES: &str = "\
+Exit codes:
+  0   success
+  1   any other failure, including deviations found by baseline check and
+      mismatches found by compare_data
+  2   the server rejected the credentials
+  3   the server could not be reached or did not answer in time
+  4   no tags were found
+  5   the server's response could not be read (malformed or too large)
+  6   the server answered with another HTTP error
+  64  invalid command-line arguments";
+
+pub const USAGE: u8 = 64;
+
+/// Why a run failed, one variant per kind of failure scripts can tell apart
+/// by the exit code; `code` maps them.
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error(transparent)]
+    Credentials(CallError),
+    #[error(transparent)]
+    Unreachable(CallError),
+    /// The run found no tags to work on, so scripts can tell an empty
+    /// historian from a failure.
+    #[error("{0}")]
+    NoTags(String),
+    #[error(transparent)]
+    Unreadable(CallError),
+    #[error(transparent)]
+    Status(CallError),
+    /// `baseline check` found deviations; the report is already printed.
+    #[error("baseline check found {0} deviations")]
+    Deviations(usize),
+    /// `compare_data` found differing windows; the report is already printed.
+    #[error("compare_data found {0} differing windows")]
+    Mismatches(usize),
+    #[error(transparent)]
+    Other(Box<dyn Error>),
+}
+
+impl CliError {
+    pub fn code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            CliError::Other(_) | CliError::Deviations(_) | CliError::Mismatches(_) => 1,
+            CliError::Credentials(_) => 2,
+            CliError::Unreachable(_) => 3,
+            CliError::NoTags(_) => 4,
+            CliError::Unreadable(_) => 5,
+            CliError::Status(_) => 6,
+        })
+    }
+}
+
+impl From<CallError> for CliError {
+    fn from(error: CallError) -> Self {
+        match error.kind() {
+            CallErrorKind::Status(401 | 403) => CliError::Credentials(error),
+            CallErrorKind::Status(_) => CliError::Status(error),
+            CallErrorKind::Unreachable | CallErrorKind::Timeout => CliError::Unreachable(error),
+            CallErrorKind::Unreadable => CliError::Unreadable(error),
+            _ => CliError::Other(error.into()),
+        }
+    }
+}
+
+/// An error's message followed by those of its sources, as the CLI prints it.
+pub fn chain_text(error: &(dyn Error + 'static)) -> String {
+    let mut text = error.to_string();
+    let mut current = error.source();
+    while let Some(error) = current {
+        text.push_str(": ");
+        text.push_str(&error.to_string());
+        current = error.source();
+    }
+    text
+}
+
+/// The client returns a failed call as a boxed `CallError`; every other
+/// error is a general failure.
+impl From<Box<dyn Error>> for CliError {
+    fn from(error: Box<dyn Error>) -> Self {
+        match error.downcast::<CallError>() {
+            Ok(error) => (*error).into(),
+            Err(error) => CliError::Other(error),
+        }
+    }
+}
+
+impl From<&str> for CliError {
+    fn from(message: &str) -> Self {
+        CliError::Other(message.into())
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        CliError::Other(message.into())
+    }
+}
+
+impl From<std::io::Error> for CliError {
+    fn from(error: std::io::Error) -> Self {
+        CliError::Other(error.into())
+    }
+}
+
+impl From<serde_json::Error> for CliError {
+    fn from(error: serde_json::Error) -> Self {
+        CliError::Other(error.into())
+    }
+}
+
+impl From<indicatif::style::TemplateError> for CliError {
+    fn from(error: indicatif::style::TemplateError) -> Self {
+        CliError::Other(error.into())
+    }
+}
@@ -0,0 +1,30 @@
+use crate::output::{self, Compression};
+
+/// Sinks other Canary tools offer that this one does not implement, so
+/// "why can't this site write to X" has an answer without reading the
+/// source.
+const MISSING: [&str; 3] = ["Parquet", "Kafka", "OPC UA"];
+
+/// Prints what this binary can write to. Nothing here is behind a Cargo
+/// feature, so the list only changes between versions, but the version,
+/// target and size tell support which build a site is running.
+pub fn print() {
+    let size = std::env::current_exe().and_then(std::fs::metadata).map(|metadata| format!(", {:.1} MB", metadata.len() as f64 / 1_048_576.0)).unwrap_or_default();
+    println!("canary-context {} ({}-{}{})", env!("CARGO_PKG_VERSION"), std::env::consts::ARCH, std::env::consts::OS, size);
+    println!("Output formats: {}", output::FORMATS.join(", "));
+    println!("Compression: {}", Compression::NAMES.join(", "));
+    println!("Sinks: ClickHouse (HTTP), QuestDB (ILP over TCP), Redis (without TLS), NATS JetStream, git");
+    println!("TLS: {} for Canary and ClickHouse, rustls for NATS", native_tls());
+    println!("Not in this build: {}", MISSING.join(", "));
+}
+
+/// The library reqwest's default TLS uses on this platform.
+fn native_tls() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "native-tls (SChannel)"
+    } else if cfg!(target_vendor = "apple") {
+        "native-tls (Security.framework)"
+    } else {
+        "native-tls (OpenSSL)"
+    }
+}
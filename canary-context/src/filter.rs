@@ -0,0 +1,87 @@
+use clap::ArgMatches;
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+
+/// Client-side tag name filters, applied after the browse and before any
+/// context is fetched. A tag must match every `--filter` and every `--glob`.
+pub struct TagFilter {
+    regexes: Vec<Regex>,
+    globs: Vec<TagGlob>,
+}
+
+/// A `--glob` pattern: `/`-separated segments matched against the
+/// `.`-separated nodes of a tag name. `*`, `?` and `[...]` stay within one
+/// node and `**` spans any number of nodes. Node names may themselves
+/// contain `/`, which `*` matches and `\/` matches literally.
+#[derive(Clone)]
+pub struct TagGlob {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone)]
+enum Segment {
+    AnyNodes,
+    Node(GlobMatcher),
+}
+
+pub fn parse_glob(pattern: &str) -> Result<TagGlob, globset::Error> {
+    let mut segments = Vec::new();
+    for segment in split_unescaped(pattern) {
+        segments.push(if segment == "**" {
+            Segment::AnyNodes
+        } else {
+            Segment::Node(GlobBuilder::new(&segment).literal_separator(false).backslash_escape(true).build()?.compile_matcher())
+        });
+    }
+    Ok(TagGlob { segments })
+}
+
+/// Splits at every `/` that is not escaped with a backslash; escapes are
+/// kept for the glob parser.
+fn split_unescaped(pattern: &str) -> Vec<String> {
+    let mut segments = vec![String::new()];
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let segment = segments.last_mut().unwrap();
+                segment.push(c);
+                segment.extend(chars.next());
+            }
+            '/' => segments.push(String::new()),
+            _ => segments.last_mut().unwrap().push(c),
+        }
+    }
+    segments
+}
+
+impl TagGlob {
+    pub fn is_match(&self, tag: &str) -> bool {
+        matches_nodes(&self.segments, &tag.split('.').collect::<Vec<_>>())
+    }
+}
+
+fn matches_nodes(segments: &[Segment], nodes: &[&str]) -> bool {
+    match segments.split_first() {
+        None => nodes.is_empty(),
+        Some((Segment::AnyNodes, rest)) => (0..=nodes.len()).any(|skip| matches_nodes(rest, &nodes[skip..])),
+        Some((Segment::Node(glob), rest)) => nodes.split_first().is_some_and(|(node, nodes)| glob.is_match(node) && matches_nodes(rest, nodes)),
+    }
+}
+
+impl TagFilter {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        TagFilter {
+            regexes: matches.get_many::<Regex>("filter").unwrap_or_default().cloned().collect(),
+            globs: matches.get_many::<TagGlob>("glob").unwrap_or_default().cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regexes.is_empty() && self.globs.is_empty()
+    }
+
+    pub fn matches(&self, tag: &str) -> bool {
+        self.regexes.iter().all(|regex| regex.is_match(tag)) && self.globs.iter().all(|glob| glob.is_match(tag))
+    }
+}
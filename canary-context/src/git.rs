@@ -0,0 +1,112 @@
+use crate::template::Template;
+use serde_json::Value;
+use std::error::Error;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Commits an export that was written into a Git working tree. The file is
+/// compared with its committed version with the `retrieved_at` field of
+/// every row blanked out, so a run that only differs in its timestamp
+/// leaves the repository untouched. Returns the commit subject, or `None`
+/// when nothing changed.
+pub fn commit_export(repo: &Path, file: &Path, tags: usize, server: &str, format: &str, template: Option<&Template>) -> Result<Option<String>, Box<dyn Error>> {
+    let relative = file.strip_prefix(repo).map_err(|_| format!("{} is not inside the Git repository {}", file.display(), repo.display()))?;
+    let relative = relative.to_str().ok_or("export path is not valid UTF-8")?;
+    let current = std::fs::read(file)?;
+
+    let previous = git(repo, &["show", &format!("HEAD:./{}", relative)]).ok().filter(|output| output.status.success()).map(|output| output.stdout);
+    let (added, removed) = match &previous {
+        Some(previous) => {
+            let (added, removed) = line_changes(&redact_run_timestamps(previous, format, template), &redact_run_timestamps(&current, format, template));
+            if added == 0 && removed == 0 {
+                // Keep the committed file so the working tree stays clean.
+                std::fs::write(file, previous)?;
+                return Ok(None);
+            }
+            (added, removed)
+        }
+        None => (String::from_utf8_lossy(&current).lines().count(), 0),
+    };
+
+    let subject = format!("Update tag inventory: {} tags from {}", tags, server);
+    let body = format!("{} lines added, {} lines removed in {}.", added, removed, relative);
+    run(repo, &["add", "--", relative])?;
+    run(repo, &["commit", "--quiet", "-m", &subject, "-m", &body, "--", relative])?;
+    Ok(Some(subject))
+}
+
+fn git(repo: &Path, args: &[&str]) -> std::io::Result<Output> {
+    Command::new("git").arg("-C").arg(repo).args(args).output()
+}
+
+fn run(repo: &Path, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let output = git(repo, args).map_err(|e| format!("cannot run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(())
+}
+
+/// Counts lines only in `new` and lines only in `old`, ignoring order.
+fn line_changes(old: &str, new: &str) -> (usize, usize) {
+    let mut remaining: Vec<&str> = old.lines().collect();
+    remaining.sort_unstable();
+    let mut added = 0;
+    for line in new.lines() {
+        match remaining.binary_search(&line) {
+            Ok(index) => {
+                remaining.remove(index);
+            }
+            Err(_) => added += 1,
+        }
+    }
+    (added, remaining.len())
+}
+
+/// Blanks the `retrieved_at` field of every row: the column of that name
+/// in CSV, the `retrievedAt` key in JSON and NDJSON, and the `RetrievedAt:`
+/// line or the template's `{retrieved_at}` in TXT. Other fields are left
+/// as written, so a server value that looks like a run timestamp still
+/// counts as a change. Files that cannot be read this way, such as binary
+/// or compressed outputs, are compared as they are.
+fn redact_run_timestamps(bytes: &[u8], format: &str, template: Option<&Template>) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let redacted = match (format, template) {
+        ("csv", _) => redact_csv(&text),
+        ("json", _) => redact_json(&text),
+        ("ndjson", _) => Some(text.lines().map(|line| redact_json_row(line).unwrap_or_else(|| line.to_string())).collect::<Vec<_>>().join("\n")),
+        ("txt", Some(template)) => Some(template.blank_retrieved_at(&text)),
+        ("txt", None) => Some(text.lines().map(|line| if line.starts_with("  RetrievedAt: ") { "  RetrievedAt:" } else { line }).collect::<Vec<_>>().join("\n")),
+        _ => None,
+    };
+    redacted.unwrap_or_else(|| text.into_owned())
+}
+
+fn redact_csv(text: &str) -> Option<String> {
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(text.as_bytes());
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut column = None;
+    for record in reader.records() {
+        let mut record = record.ok()?;
+        let column = *column.get_or_insert_with(|| record.iter().position(|header| header == "retrieved_at"));
+        if let Some(index) = column.filter(|index| *index < record.len()) {
+            record = record.iter().enumerate().map(|(i, field)| if i == index { "" } else { field }).collect();
+        }
+        writer.write_record(&record).ok()?;
+    }
+    String::from_utf8(writer.into_inner().ok()?).ok()
+}
+
+fn redact_json(text: &str) -> Option<String> {
+    let mut rows: Value = serde_json::from_str(text).ok()?;
+    for row in rows.as_array_mut()? {
+        row.as_object_mut()?.remove("retrievedAt");
+    }
+    serde_json::to_string_pretty(&rows).ok()
+}
+
+fn redact_json_row(line: &str) -> Option<String> {
+    let mut row: Value = serde_json::from_str(line).ok()?;
+    row.as_object_mut()?.remove("retrievedAt");
+    serde_json::to_string(&row).ok()
+}
@@ -0,0 +1,42 @@
+use crate::exit::{chain_text, CliError};
+use canary_context::{CallError, CallErrorKind};
+
+/// Suggests what to check for the failures people run into most: the wrong
+/// host, port, scheme, token or API version, or a stopped Views service.
+/// Only failed Canary calls get a hint, since the flags it names are about
+/// the Canary server; sink failures and anything else are reported as is.
+pub fn for_error(error: &CliError, api_version: &str) -> Option<String> {
+    match error {
+        CliError::Credentials(call) | CliError::Status(call) => match call.kind() {
+            CallErrorKind::Status(status) => status_hint(call.endpoint(), status, api_version),
+            _ => None,
+        },
+        CliError::Unreachable(call) => transport_hint(call),
+        _ => None,
+    }
+}
+
+fn status_hint(endpoint: &str, status: u16, api_version: &str) -> Option<String> {
+    match status {
+        401 | 403 => Some("the server rejected the credentials; check --api_token (or --username and --password) and that they are still valid in Canary Identity".to_string()),
+        404 => Some(format!("the server has no {} endpoint under /{}; check --api_version (Canary 22 and later serve api/v2, older servers api/v1)", endpoint, api_version)),
+        503 => Some("the Canary Views service is unavailable; check that it is running on the server, then try again".to_string()),
+        _ => None,
+    }
+}
+
+fn transport_hint(error: &CallError) -> Option<String> {
+    let chain = chain_text(error).to_lowercase();
+    let hint = if chain.contains("dns error") || chain.contains("failed to lookup address") {
+        "the host name could not be resolved; check the host in --canary"
+    } else if chain.contains("certificate") || chain.contains("tls") || chain.contains("ssl") {
+        "the TLS handshake failed; check that --canary uses https for the Views API's https port (55236) and http for its http port (55235)"
+    } else if chain.contains("connection refused") {
+        "nothing is listening at that address; check the port in --canary (the Views API listens on 55235 for http and 55236 for https)"
+    } else if error.kind() == CallErrorKind::Timeout {
+        "the server did not answer in time; check that --canary points at the Views API and that no firewall drops the connection"
+    } else {
+        return None;
+    };
+    Some(hint.to_string())
+}
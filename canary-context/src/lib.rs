@@ -0,0 +1,8 @@
+pub mod audit;
+mod client;
+pub mod quality;
+pub mod request;
+pub mod response;
+pub mod secret;
+
+pub use client::{CallError, CallErrorKind, CanaryClient, CanaryClientBuilder, LiveSession};
@@ -0,0 +1,52 @@
+use clap::ArgMatches;
+use std::fmt;
+use std::io;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Sends this tool's events to stderr: warnings and status by default,
+/// only errors with --quiet, call details with -v and everything with -vv.
+/// Other crates only log their warnings and errors.
+pub fn init(matches: &ArgMatches) {
+    let level = match (matches.get_flag("quiet"), matches.get_count("verbose")) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    let filter = Targets::new().with_target("canary_context", level).with_default(level.min(LevelFilter::WARN));
+    let json = matches.get_one::<String>("log_format").is_some_and(|format| format == "json");
+    let (json, text) = if json {
+        (Some(tracing_subscriber::fmt::layer().json().with_writer(io::stderr)), None)
+    } else {
+        (None, Some(tracing_subscriber::fmt::layer().event_format(Text).with_ansi(false).with_writer(io::stderr)))
+    };
+    tracing_subscriber::registry().with(json).with(text).with(filter).init();
+}
+
+/// The tool's own message style: "Warning: ..." and "Error: ...", status
+/// lines as they are, and fields as `key=value` after the message.
+struct Text;
+
+impl<S, N> FormatEvent<S, N> for Text
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "Error: ")?,
+            Level::WARN => write!(writer, "Warning: ")?,
+            Level::INFO => {}
+            Level::DEBUG => write!(writer, "Debug: ")?,
+            Level::TRACE => write!(writer, "Trace: ")?,
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
@@ -1,184 +1,1469 @@
-use clap::{Arg, Command};
+mod baseline;
+mod clickhouse;
+mod compare;
+mod config;
+mod encoding;
+mod estimate;
+mod examples;
+mod exit;
+mod features;
+mod filter;
+mod git;
+mod hint;
+mod logging;
+mod nats;
+mod output;
+mod questdb;
+mod redis_cache;
+mod self_test;
+mod similar;
+mod sqlite;
+mod tag_list;
+mod template;
+
+use baseline::{Baseline, Field};
+use config::Config;
+use encoding::Encoding;
+use exit::CliError;
+use filter::TagFilter;
+use canary_context::request::{BrowseRequest, TagDataRequest};
+use canary_context::audit::AuditLog;
+use canary_context::quality::QualityTable;
+use canary_context::response::{TagContext, TagValue};
+use canary_context::{CallError, CanaryClient, CanaryClientBuilder};
+use output::{ByteCount, Compression, DataRecord, Record, RowId, WriteOptions};
+use std::collections::HashMap;
+use chrono::SecondsFormat;
+use futures_util::StreamExt;
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use tag_list::TagParams;
+use template::Template;
+use std::collections::{BTreeSet, HashSet};
 use std::error::Error;
 use std::fs::File;
-use std::io::Write;
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct TagContext {
-    tag_name: String,
-    tag_context: TagDetails,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct TagDetails {
-    historian_item_id: Option<String>,
-    source_item_id: Option<String>,
-    oldest_time_stamp: String,
-    latest_time_stamp: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct ApiResponse {
-    data: Vec<TagContext>,
-}
-
-async fn get_tags(client: &Client, canary: &str, api_version: &str, api_token: &str, application: &str, timezone: &str) -> Result<Vec<String>, Box<dyn Error>> {
-    let url = format!("{}/{}", canary, api_version);
-    let payload = serde_json::json!({
-        "application": application,
-        "timezone": timezone,
-        "apiToken": api_token,
-        "path": "",
-        "deep": true,
-        "search": ""
-    });
-
-    let response = client.post(format!("{}/browseTags", url))
-        .json(&payload)
-        .send()
-        .await?
-        .json::<serde_json::Value>()
-        .await?;
+use std::future::Future;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::process::ExitCode;
+use std::time::Duration;
 
-    let tags = response["tags"]
-        .as_array()
-        .unwrap_or(&vec![])
-        .iter()
-        .filter_map(|tag| tag.as_str().map(String::from))
-        .collect();
+const CONTEXT_ERRORS_FILE: &str = "context_errors.csv";
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY_FACTOR: u32 = 32;
+/// Arguments that choose the tags of export and tags, kept as lineage.
+const BROWSE_QUERY: [&str; 11] = ["application", "timezone", "historian", "path", "no_deep", "search", "tags_file", "filter", "glob", "max_tags", "truncate_tags"];
+/// Arguments that shape a data read, kept as lineage.
+const DATA_QUERY: [&str; 7] = ["tags", "start_time", "end_time", "aggregate", "aggregate_interval", "bounds", "max_size"];
 
-    Ok(tags)
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid size '{}'", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        other => return Err(format!("unknown size unit '{}'", other)),
+    };
+    number.checked_mul(multiplier).ok_or_else(|| format!("size '{}' is too large", value))
 }
 
-async fn get_tag_context(client: &Client, canary: &str, api_version: &str, api_token: &str, tags: Vec<String>) -> Result<Vec<TagContext>, Box<dyn Error>> {
-    let url = format!("{}/{}", canary, api_version);
-    let payload = serde_json::json!({
-        "apiToken": api_token,
-        "tags": tags
-    });
-
-    let response = client.post(format!("{}/getTagContext", url))
-        .json(&payload)
-        .send()
-        .await?
-        .json::<ApiResponse>()
-        .await?;
-
-    Ok(response.data)
-}
-
-fn save_to_csv(data: &Vec<TagContext>, filename: &str) -> Result<(), Box<dyn Error>> {
-    let mut wtr = csv::Writer::from_path(filename)?;
-    wtr.write_record(["tag_name", "historian_item_id", "source_item_id", "oldest_time_stamp", "latest_time_stamp"])?;
-
-    for item in data {
-        wtr.write_record([
-            &item.tag_name,
-            item.tag_context.historian_item_id.as_deref().unwrap_or(""),
-            item.tag_context.source_item_id.as_deref().unwrap_or(""),
-            &item.tag_context.oldest_time_stamp,
-            &item.tag_context.latest_time_stamp,
-        ])?;
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("invalid rate '{}'; expected a positive number of calls per second", value)),
     }
-
-    wtr.flush()?;
-    Ok(())
 }
 
-fn save_to_txt(data: &Vec<TagContext>, filename: &str) -> Result<(), Box<dyn Error>> {
-    let mut file = File::create(filename)?;
-
-    for item in data {
-        writeln!(file, "TagName: {}", item.tag_name)?;
-        writeln!(file, "  HistorianItemId: {}", item.tag_context.historian_item_id.as_deref().unwrap_or(""))?;
-        writeln!(file, "  SourceItemId: {}", item.tag_context.source_item_id.as_deref().unwrap_or(""))?;
-        writeln!(file, "  OldestTimeStamp: {}", item.tag_context.oldest_time_stamp)?;
-        writeln!(file, "  LatestTimeStamp: {}", item.tag_context.latest_time_stamp)?;
-        writeln!(file)?;
-    }
-
-    Ok(())
-}
-
-fn save_to_json(data: &Vec<TagContext>, filename: &str) -> Result<(), Box<dyn Error>> {
-    let file = File::create(filename)?;
-    serde_json::to_writer_pretty(file, data)?;
-    Ok(())
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let matches = Command::new("Canary CLI")
-        .version("1.0")
+fn cli() -> Command {
+    Command::new("canary-context")
+        .version(env!("CARGO_PKG_VERSION"))
         .about("CLI tool to interact with the Canary API")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(Arg::new("config")
+            .long("config")
+            .env("CANARY_CONFIG")
+            .value_parser(clap::value_parser!(String))
+            .global(true)
+            .help("Config file with connection profiles [default: ~/.config/canary-context/config.toml]"))
+        .arg(Arg::new("profile")
+            .long("profile")
+            .env("CANARY_PROFILE")
+            .value_parser(clap::value_parser!(String))
+            .global(true)
+            .help("Take --canary, --api_version, --api_token, --application, --timezone and any per-subcommand flag defaults from this config file profile unless given on the command line or in the environment"))
         .arg(Arg::new("canary")
             .long("canary")
+            .env("CANARY_URL")
             .value_parser(clap::value_parser!(String))
-            .required(true)
-            .help("Base URL for the Canary server"))
+            .value_delimiter(',')
+            .action(ArgAction::Append)
+            .global(true)
+            .help("Base URL for the Canary server; give several (comma-separated or repeated) to fail over in order"))
         .arg(Arg::new("api_version")
             .long("api_version")
+            .env("CANARY_API_VERSION")
             .value_parser(clap::value_parser!(String))
             .default_value("api/v2")
+            .global(true)
             .help("API version to use"))
         .arg(Arg::new("api_token")
             .long("api_token")
+            .env("CANARY_API_TOKEN")
             .value_parser(clap::value_parser!(String))
-            .required(true)
-            .help("API token for authentication"))
-        .arg(Arg::new("application")
+            .hide_default_value(true)
+            .hide_env_values(true)
+            .global(true)
+            .help("API token for authentication; prefer CANARY_API_TOKEN or a profile, which keep it out of shell history and process lists"))
+        .arg(Arg::new("username")
+            .long("username")
+            .env("CANARY_USERNAME")
+            .value_parser(clap::value_parser!(String))
+            .global(true)
+            .help("Log in as this Canary user with getUserToken instead of using an API token"))
+        .arg(Arg::new("password")
+            .long("password")
+            .env("CANARY_PASSWORD")
+            .value_parser(clap::value_parser!(String))
+            .hide_env_values(true)
+            .global(true)
+            .help("Password for --username; prompted for on a terminal when not given"))
+        .arg(Arg::new("audit_log")
+            .long("audit_log")
+            .value_parser(clap::value_parser!(String))
+            .global(true)
+            .help("Append a JSONL record of every API call to this file (tokens redacted)"))
+        .arg(Arg::new("max_response_size")
+            .long("max_response_size")
+            .value_parser(parse_size)
+            .global(true)
+            .help("Abort if a single response body exceeds this size (bytes, or with a KB/MB/GB suffix)"))
+        .arg(Arg::new("batch_size")
+            .long("batch_size")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .default_value("500")
+            .global(true)
+            .help("Most tags per getTagContext request; longer tag lists are fetched in several requests"))
+        .arg(Arg::new("concurrency")
+            .long("concurrency")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .default_value("4")
+            .global(true)
+            .help("Most getTagContext requests in flight at once"))
+        .arg(Arg::new("retries")
+            .long("retries")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u32))
+            .default_value("3")
+            .global(true)
+            .help("Retry an API call up to N times after a 408, 429, 502, 503 or 504 response or a connection failure"))
+        .arg(Arg::new("retry_delay")
+            .long("retry_delay")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("500")
+            .global(true)
+            .help("Milliseconds to wait before the first retry; doubled for each further one, with jitter"))
+        .arg(Arg::new("rate_limit")
+            .long("rate_limit")
+            .value_name("PER_SECOND")
+            .value_parser(parse_rate)
+            .global(true)
+            .help("Send at most this many API calls per second, e.g. 5 or 0.5, across all concurrent requests"))
+        .arg(Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("verbose")
+            .global(true)
+            .help("Only log errors, and do not show progress bars (they are only shown on a terminal anyway)"))
+        .arg(Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::Count)
+            .global(true)
+            .help("Also log every API call with its URL, status, duration and response size; twice to also log request bodies (tokens redacted)"))
+        .arg(Arg::new("log_format")
+            .long("log_format")
+            .value_parser(PossibleValuesParser::new(["text", "json"]))
+            .default_value("text")
+            .global(true)
+            .help("Format of the warnings and diagnostics written to stderr"))
+        .arg(Arg::new("max_requests")
+            .long("max_requests")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64))
+            .global(true)
+            .help("Abort once N API calls have been made in this run, counting retries, logins and keep-alives; revoking a token is always allowed"))
+        .arg(Arg::new("compress_requests")
+            .long("compress_requests")
+            .action(ArgAction::SetTrue)
+            .global(true)
+            .help("Gzip request bodies (only if the server accepts Content-Encoding: gzip)"))
+        .subcommand(Command::new("browse")
+            .about("List the tag names the server browses to, one per line")
+            .args(browse_args()))
+        .subcommand(Command::new("near_duplicates")
+            .about("List tags whose names are suspiciously similar to another tag's in the same folder, e.g. typo'd duplicates")
+            .args(browse_args())
+            .arg(Arg::new("max_distance")
+                .long("max_distance")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("2")
+                .help("Most single-character edits between two names to report them")))
+        .subcommand(Command::new("tags")
+            .about("Browse all tags and write only their names to a file, without fetching context")
+            .args(browse_args())
+            .args(output_args()))
+        .subcommand(Command::new("context")
+            .about("Print the context of the given tags as JSON")
+            .arg(Arg::new("tags")
+                .value_parser(clap::value_parser!(String))
+                .num_args(1..)
+                .required(true)
+                .help("Tag names to look up")))
+        .subcommand(Command::new("lookup")
+            .about("Find the current tags of historian item IDs, e.g. ones found in old exports, and print their context as JSON")
+            .arg(Arg::new("historian_item_ids")
+                .value_parser(clap::value_parser!(String))
+                .num_args(1..)
+                .required(true)
+                .help("Historian item IDs to look up"))
+            .args(browse_args()))
+        .subcommand(Command::new("export")
+            .about("Browse all tags, fetch their context and write it to a file and any configured sinks")
+            .args(browse_args())
+            .args(output_args())
+            .args(export_args())
+            .args(clickhouse_args("canary_tag_context")))
+        .subcommand(Command::new("data")
+            .about("Read the raw history of the given tags and write timestamp/value/quality rows")
+            .arg(Arg::new("tags")
+                .value_parser(clap::value_parser!(String))
+                .num_args(1..)
+                .required(true)
+                .help("Tag names to read"))
+            .arg(Arg::new("start_time")
+                .long("start_time")
+                .value_parser(clap::value_parser!(String))
+                .required(true)
+                .help("Start of the time range: a timestamp or a relative time such as Now-1Day"))
+            .arg(Arg::new("end_time")
+                .long("end_time")
+                .value_parser(clap::value_parser!(String))
+                .default_value("Now")
+                .help("End of the time range"))
+            .arg(Arg::new("aggregate")
+                .long("aggregate")
+                .value_parser(clap::value_parser!(String))
+                .requires("aggregate_interval")
+                .help("Export processed values of this Canary aggregate (e.g. TimeAverage2, Minimum, Maximum) instead of raw samples"))
+            .arg(Arg::new("aggregate_interval")
+                .long("aggregate_interval")
+                .value_parser(clap::value_parser!(String))
+                .requires("aggregate")
+                .help("Interval of each aggregate value as a time span, e.g. 1:00:00 for hourly"))
+            .arg(Arg::new("bounds")
+                .long("bounds")
+                .value_parser(PossibleValuesParser::new(["inside", "outside"]))
+                .default_value("inside")
+                .help("Which samples to read: inside the range (start inclusive, end exclusive), or outside to also get the bounding samples before the start and after the end"))
+            .arg(Arg::new("max_size")
+                .long("max_size")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("Most samples per tag to ask for in one request; longer series are read in continued pages"))
+            .arg(Arg::new("refresh_qualities")
+                .long("refresh_qualities")
+                .action(ArgAction::SetTrue)
+                .help("Ask the server for the names of the quality codes in the data instead of using only the built-in OPC names"))
+            .arg(Arg::new("estimate")
+                .long("estimate")
+                .action(ArgAction::SetTrue)
+                .help("Read a first page of --max_size samples per tag (1000 by default), print the projected samples, output size and read time of the whole range, and ask before reading it; needs timestamps or Now-relative times"))
+            .arg(Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .action(ArgAction::SetTrue)
+                .requires("estimate")
+                .help("Go ahead after --estimate without asking"))
+            .args(output_args())
+            .args(clickhouse_args("canary_tag_data"))
+            .arg(Arg::new("questdb_addr")
+                .long("questdb_addr")
+                .value_name("HOST:PORT")
+                .value_parser(clap::value_parser!(String))
+                .help("Also send the samples to QuestDB as line protocol over TCP, e.g. questdb:9009"))
+            .arg(Arg::new("questdb_table")
+                .long("questdb_table")
+                .value_parser(clap::value_parser!(String))
+                .default_value("canary_tag_data")
+                .help("QuestDB table to write to; it is created on the first write with tag_name as a symbol column")))
+        .subcommand(Command::new("compare_data")
+            .about("Read the same tags and time range from --canary and a second server, e.g. after a migration, and report the windows whose sample counts or values differ")
+            .arg(Arg::new("tags")
+                .value_parser(clap::value_parser!(String))
+                .num_args(1..)
+                .required(true)
+                .help("Tag names to compare"))
+            .arg(Arg::new("other_canary")
+                .long("other_canary")
+                .value_parser(clap::value_parser!(String))
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .required(true)
+                .help("Base URL of the server to compare against, with failover like --canary"))
+            .arg(Arg::new("other_api_token")
+                .long("other_api_token")
+                .env("CANARY_OTHER_API_TOKEN")
+                .value_parser(clap::value_parser!(String))
+                .hide_env_values(true)
+                .help("API token for --other_canary [default: the credentials given for --canary]"))
+            .arg(Arg::new("start_time")
+                .long("start_time")
+                .value_parser(clap::value_parser!(String))
+                .required(true)
+                .help("Start of the time range: a timestamp or a relative time such as Now-1Day"))
+            .arg(Arg::new("end_time")
+                .long("end_time")
+                .value_parser(clap::value_parser!(String))
+                .default_value("Now")
+                .help("End of the time range"))
+            .arg(Arg::new("window")
+                .long("window")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("3600")
+                .help("Length of each compared window; windows start at multiples of it since 1970-01-01 UTC"))
+            .arg(Arg::new("max_size")
+                .long("max_size")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("Most samples per tag to ask for in one request; longer series are read in continued pages")))
+        .subcommand(Command::new("live")
+            .about("Stream new samples of the given tags as NDJSON until interrupted")
+            .arg(Arg::new("tags")
+                .value_parser(clap::value_parser!(String))
+                .num_args(1..)
+                .required(true)
+                .help("Tag names to follow"))
+            .arg(Arg::new("output_file")
+                .long("output_file")
+                .value_parser(clap::value_parser!(String))
+                .help("Append samples to this file instead of writing them to stdout (-)"))
+            .arg(Arg::new("poll_interval")
+                .long("poll_interval")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("1000")
+                .help("Milliseconds to wait between polls of the live session"))
+            .arg(Arg::new("polls")
+                .long("polls")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Stop after this many polls instead of running until interrupted")))
+        .subcommand(Command::new("baseline")
+            .about("Record or verify an approved tag inventory")
+            .subcommand_required(true)
+            .subcommand(Command::new("write")
+                .about("Save the live tag inventory as the approved baseline")
+                .args(browse_args())
+                .arg(Arg::new("file").required(true).help("Baseline file to write")))
+            .subcommand(Command::new("check")
+                .about("Compare the live tag inventory with an approved baseline")
+                .args(browse_args())
+                .arg(Arg::new("key")
+                    .long("key")
+                    .value_parser(PossibleValuesParser::new(Field::ALL.map(Field::column)))
+                    .default_value("tag_name")
+                    .help("Field that matches baseline tags to live tags; use source_item_id to follow tags across renames"))
+                .arg(Arg::new("compare")
+                    .long("compare")
+                    .value_parser(PossibleValuesParser::new(Field::ALL.map(Field::column)))
+                    .value_delimiter(',')
+                    .action(ArgAction::Append)
+                    .help("Fields that count as changed when they differ (comma-separated or repeated) [default: all but --key]"))
+                .arg(Arg::new("diff_format")
+                    .long("diff_format")
+                    .value_parser(PossibleValuesParser::new(["text", "json"]))
+                    .default_value("text")
+                    .help("Report deviations as text lines or as one JSON document"))
+                .arg(Arg::new("no_color")
+                    .long("no_color")
+                    .action(ArgAction::SetTrue)
+                    .help("Do not color text deviations (also off when NO_COLOR is set or stdout is not a terminal)"))
+                .arg(Arg::new("file").required(true).help("Baseline file to check against"))))
+        .subcommand(Command::new("revoke_token")
+            .alias("revoke-token")
+            .about("Revoke a user token, e.g. a leaked or stale one; needs no credentials besides the token")
+            .arg(Arg::new("token")
+                .value_parser(clap::value_parser!(String))
+                .required(true)
+                .help("User token to revoke, or - to read it from stdin and keep it out of shell history")))
+        .subcommand(Command::new("examples")
+            .about("Print example invocations of every subcommand for the current profile"))
+        .subcommand(Command::new("features")
+            .about("Print the output formats, sinks and TLS backends this binary was built with, and the ones it lacks"))
+        .subcommand(Command::new("self_test")
+            .about("Check this binary end to end against a built-in mock server: argument parsing, the client, batching and every writer; needs no network"))
+        .subcommand(Command::new("version")
+            .about("Print the version of this tool"))
+}
+
+/// Flags that choose which tags are browsed.
+fn browse_args() -> [Arg; 12] {
+    [
+        Arg::new("application")
             .long("application")
+            .env("CANARY_APPLICATION")
             .value_parser(clap::value_parser!(String))
             .default_value("Postman Test")
-            .help("Application name"))
-        .arg(Arg::new("timezone")
+            .help("Application name"),
+        Arg::new("timezone")
             .long("timezone")
+            .env("CANARY_TIMEZONE")
             .value_parser(clap::value_parser!(String))
             .default_value("Pacific Standard Time")
-            .help("Timezone to use"))
-        .arg(Arg::new("output_format")
-            .long("output_format")
+            .help("Timezone to use"),
+        Arg::new("historian")
+            .long("historian")
+            .value_parser(clap::value_parser!(String))
+            .action(ArgAction::Append)
+            .help("Only browse tags of this historian (repeat to browse several; adds a historian column)"),
+        Arg::new("path")
+            .long("path")
             .value_parser(clap::value_parser!(String))
+            .help("Only browse below this node, e.g. a dataset; with --historian, relative to each historian"),
+        Arg::new("no_deep")
+            .long("no_deep")
+            .action(ArgAction::SetTrue)
+            .help("Only list the tags directly in --path, not in the nodes below it"),
+        Arg::new("search")
+            .long("search")
+            .value_parser(clap::value_parser!(String))
+            .default_value("")
+            .help("Server-side search filter applied to tag names"),
+        Arg::new("tags_file")
+            .long("tags_file")
+            .value_name("FILE")
+            .value_parser(clap::value_parser!(String))
+            .conflicts_with_all(["historian", "path", "no_deep", "search", "retry_on_empty"])
+            .help("Read tag names from this file, one per line, or from stdin with -, instead of browsing; a CSV with a tag_name header can add alias, dataset and unit columns for export to write next to each tag"),
+        Arg::new("filter")
+            .long("filter")
+            .value_name("REGEX")
+            .value_parser(regex::Regex::new)
+            .action(ArgAction::Append)
+            .help("Only keep browsed tags whose name matches this regex, e.g. 'Plant1.*Temperature' (repeat to require several)"),
+        Arg::new("glob")
+            .long("glob")
+            .value_parser(filter::parse_glob)
+            .action(ArgAction::Append)
+            .help("Only keep browsed tags whose name matches this glob, with nodes separated by /, e.g. 'Plant1/*/Temp*'; write \\/ for a / inside a node name (repeat to require several)"),
+        Arg::new("retry_on_empty")
+            .long("retry_on_empty")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u32))
+            .default_value("0")
+            .help("Retry a browse that returns no tags up to N times, waiting 1s, 2s, 4s, ... in between"),
+        Arg::new("max_tags")
+            .long("max_tags")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Abort if the browse (after filters) yields more than N tags, e.g. when it was scoped too widely"),
+        Arg::new("truncate_tags")
+            .long("truncate_tags")
+            .action(ArgAction::SetTrue)
+            .requires("max_tags")
+            .help("Keep the first --max_tags tags with a warning instead of aborting"),
+    ]
+}
+
+/// Output file flags shared by the commands that write rows.
+fn output_args() -> Vec<Arg> {
+    vec![
+        Arg::new("output_format")
+            .long("output_format")
+            .value_parser(PossibleValuesParser::new(output::FORMATS))
             .required(true)
-            .help("Output format for saving the data"))
-        .arg(Arg::new("output_file")
+            .help("Output format for saving the data"),
+        Arg::new("output_file")
             .long("output_file")
             .value_parser(clap::value_parser!(String))
             .required(true)
-            .help("Output file name"))
-        .get_matches();
+            .help("Output file name, or - to write to stdout"),
+        Arg::new("json_compact")
+            .long("json_compact")
+            .action(ArgAction::SetTrue)
+            .help("Write JSON output on a single line instead of pretty-printed"),
+        Arg::new("no_formula_escape")
+            .long("no_formula_escape")
+            .action(ArgAction::SetTrue)
+            .help("Write CSV cells starting with =, +, -, @ verbatim instead of prefixing them with '"),
+        Arg::new("bom")
+            .long("bom")
+            .action(ArgAction::SetTrue)
+            .help("Start CSV and TXT output with a byte order mark (for Excel on Windows)"),
+        Arg::new("encoding")
+            .long("encoding")
+            .value_parser(PossibleValuesParser::new(Encoding::NAMES))
+            .default_value("utf-8")
+            .help("Character encoding of CSV and TXT output; characters windows-1252 cannot represent are written as ?"),
+        Arg::new("max_output_bytes")
+            .long("max_output_bytes")
+            .value_parser(parse_size)
+            .help("Abort writing the output file once it would exceed this size (bytes, or with a KB/MB/GB suffix; the compressed size with --compress); the file is left incomplete"),
+        Arg::new("compress")
+            .long("compress")
+            .value_parser(PossibleValuesParser::new(["gzip", "zstd", "none"]))
+            .help("Compress the output file [default: gzip for a .gz file name, zstd for .zst, else none]"),
+        Arg::new("locale")
+            .long("locale")
+            .value_parser(PossibleValuesParser::new(output::LOCALES.map(|(name, _)| name)))
+            .help("Write xlsx timestamps as Excel dates in this locale's day and month order; numbers are Excel numbers either way, shown with the separators of the reader's Excel"),
+        Arg::new("auto_migrate")
+            .long("auto_migrate")
+            .action(ArgAction::SetTrue)
+            .help("Upgrade the tables of an sqlite output database written by an older release; without it such a database is left as it is and the run fails"),
+    ]
+}
+
+/// ClickHouse sink flags of `export` and `data`, which insert into
+/// different tables by default.
+fn clickhouse_args(default_table: &'static str) -> [Arg; 3] {
+    [
+        Arg::new("clickhouse_url")
+            .long("clickhouse_url")
+            .value_parser(clap::value_parser!(String))
+            .help("Also insert the exported rows through this ClickHouse HTTP endpoint, e.g. http://host:8123; a user and password in the URL are sent as basic auth"),
+        Arg::new("clickhouse_table")
+            .long("clickhouse_table")
+            .value_parser(clap::value_parser!(String))
+            .default_value(default_table)
+            .help("ClickHouse table to insert into; columns are matched to the CSV column names"),
+        Arg::new("clickhouse_batch_size")
+            .long("clickhouse_batch_size")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("10000")
+            .help("Rows per ClickHouse insert request"),
+    ]
+}
 
-    let canary = matches.get_one::<String>("canary").unwrap();
-    let api_version = matches.get_one::<String>("api_version").unwrap();
-    let api_token = matches.get_one::<String>("api_token").unwrap();
+/// Sink and row flags only `export` has.
+fn export_args() -> Vec<Arg> {
+    vec![
+        Arg::new("txt_template")
+            .long("txt_template")
+            .value_parser(clap::value_parser!(String))
+            .help("File with the TXT layout of one record, using {tag_name}, {latest_time_stamp}, ... placeholders"),
+        Arg::new("redis_url")
+            .long("redis_url")
+            .value_parser(clap::value_parser!(String))
+            .help("Also write each tag's context to a Redis hash, e.g. redis://host:6379/0"),
+        Arg::new("redis_prefix")
+            .long("redis_prefix")
+            .value_parser(clap::value_parser!(String))
+            .default_value("canary:tag:")
+            .help("Prefix of the Redis key for each tag; the tag name is appended"),
+        Arg::new("redis_ttl")
+            .long("redis_ttl")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("3600")
+            .help("Seconds until a tag's Redis hash expires unless refreshed"),
+        Arg::new("nats_url")
+            .long("nats_url")
+            .value_parser(clap::value_parser!(String))
+            .help("Also publish each exported row to NATS JetStream, e.g. nats://host:4222"),
+        Arg::new("nats_subject")
+            .long("nats_subject")
+            .value_parser(clap::value_parser!(String))
+            .default_value("canary.context")
+            .help("JetStream subject to publish rows to; a stream must capture it"),
+        Arg::new("git_commit")
+            .long("git_commit")
+            .value_name("REPO_PATH")
+            .value_parser(clap::value_parser!(String))
+            .help("Write the output file into this Git working tree and commit it when the inventory changed"),
+        Arg::new("row_id")
+            .long("row_id")
+            .value_parser(PossibleValuesParser::new(["hash", "uuid"]))
+            .help("Add a stable row ID derived from the server URL and tag name"),
+        Arg::new("strict")
+            .long("strict")
+            .action(ArgAction::SetTrue)
+            .help("Fail the export if any tag returns no context"),
+        Arg::new("errors_file")
+            .long("errors_file")
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("CSV file listing the tags whose context could not be fetched, with the reason [default: context_errors.csv next to the output file, or in the current directory for stdout]"),
+    ]
+}
+
+/// Status lines go to stdout, or to stderr when the output itself does.
+fn report(output_file: &str, line: std::fmt::Arguments) {
+    if output_file == output::STDOUT {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+fn output_name(output_file: &str) -> &str {
+    if output_file == output::STDOUT { "stdout" } else { output_file }
+}
+
+/// The summary's size of the written file, and for a compressed file also
+/// the size before compression and the ratio; nothing to measure on stdout.
+fn output_size(output_file: &str, uncompressed: Option<&ByteCount>) -> Result<String, Box<dyn Error>> {
+    if output_file == output::STDOUT {
+        return Ok(String::new());
+    }
+    let bytes = std::fs::metadata(output_file)?.len();
+    Ok(match uncompressed.map(ByteCount::get) {
+        Some(uncompressed) if bytes > 0 => format!(", {} bytes ({} uncompressed, ratio {:.1}:1)", bytes, uncompressed, uncompressed as f64 / bytes as f64),
+        _ => format!(", {} bytes", bytes),
+    })
+}
+
+fn write_options(matches: &ArgMatches) -> Result<WriteOptions, Box<dyn Error>> {
+    let encoding = Encoding::from_name(matches.get_one::<String>("encoding").unwrap()).unwrap();
+    let output_file = matches.get_one::<String>("output_file").unwrap();
+    let compression = match matches.get_one::<String>("compress").map(String::as_str) {
+        Some("gzip") => Some(Compression::Gzip),
+        Some("zstd") => Some(Compression::Zstd),
+        Some(_) => None,
+        None => Compression::from_extension(output_file),
+    };
+    match matches.get_one::<String>("output_format").unwrap().as_str() {
+        "sqlite" if output_file == output::STDOUT => return Err("sqlite output needs a database file; --output_file - only works with the other formats".into()),
+        "sqlite" if compression.is_some() => return Err("sqlite output cannot be compressed; the database is updated in place".into()),
+        "json" | "ndjson" if encoding != Encoding::Utf8 => return Err("JSON output is always UTF-8; --encoding only applies to csv and txt".into()),
+        format @ ("xlsx" | "arrow" | "sqlite") if encoding != Encoding::Utf8 => return Err(format!("{} output has no text encoding; --encoding only applies to csv and txt", format).into()),
+        _ => {}
+    }
+    let locale = matches.get_one::<String>("locale");
+    if locale.is_some() && matches.get_one::<String>("output_format").unwrap() != "xlsx" {
+        return Err("--locale only applies to xlsx output".into());
+    }
+    if matches.get_flag("bom") && !encoding.has_bom() {
+        return Err("--bom cannot be used with --encoding windows-1252, which has no byte order mark".into());
+    }
+    Ok(WriteOptions {
+        escape_formulas: !matches.get_flag("no_formula_escape"),
+        bom: matches.get_flag("bom"),
+        compact_json: matches.get_flag("json_compact"),
+        encoding,
+        max_bytes: matches.get_one::<u64>("max_output_bytes").copied(),
+        compression,
+        uncompressed: ByteCount::default(),
+        metadata: HashMap::new(),
+        date_format: locale.and_then(|locale| output::LOCALES.iter().find(|(name, _)| name == locale)).map(|(_, layout)| *layout),
+    })
+}
+
+/// Lineage of a run for Arrow file metadata: the server that answered, the
+/// API and tool versions, the run ID and the given `query` arguments as a
+/// JSON object of their raw values, a list for an argument given several.
+fn lineage(matches: &ArgMatches, client: &CanaryClient, query: &[&str]) -> HashMap<String, String> {
+    let query: serde_json::Map<String, serde_json::Value> = query
+        .iter()
+        .filter_map(|name| {
+            let mut values: Vec<String> = matches.try_get_raw(name).ok().flatten()?.map(|value| value.to_string_lossy().into_owned()).collect();
+            Some((name.to_string(), if values.len() == 1 { values.remove(0).into() } else { values.into() }))
+        })
+        .collect();
+    HashMap::from([
+        ("server".to_string(), client.server().to_string()),
+        ("api_version".to_string(), matches.get_one::<String>("api_version").unwrap().clone()),
+        ("query".to_string(), serde_json::Value::Object(query).to_string()),
+        ("tool_version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+        ("run_id".to_string(), client.run_id().to_string()),
+    ])
+}
+
+/// Runs a subcommand's work with `client` and closes the client on every
+/// path, so a failed run still revokes its user token. When the work fails,
+/// its error is returned and one from closing is only logged.
+async fn closing<T>(client: &CanaryClient, work: impl Future<Output = Result<T, CliError>>) -> Result<T, CliError> {
+    let result = work.await;
+    let closed = client.close().await;
+    match (result, closed) {
+        (Ok(value), Ok(())) => Ok(value),
+        (Ok(_), Err(e)) => Err(e.into()),
+        (Err(e), closed) => {
+            if let Err(close) = closed {
+                tracing::warn!("could not close the session: {}", exit::chain_text(close.as_ref()));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// The --canary servers in failover order.
+fn servers(matches: &ArgMatches) -> Result<Vec<&String>, Box<dyn Error>> {
+    Ok(matches.get_many::<String>("canary").ok_or("--canary is required")?.collect())
+}
+
+fn password(matches: &ArgMatches, username: &str) -> Result<String, Box<dyn Error>> {
+    if let Some(password) = matches.get_one::<String>("password") {
+        return Ok(password.clone());
+    }
+    if !io::stdin().is_terminal() {
+        return Err("--password or CANARY_PASSWORD is required with --username when not run from a terminal".into());
+    }
+    Ok(rpassword::prompt_password(format!("Canary password for {}: ", username))?)
+}
+
+async fn connect(matches: &ArgMatches) -> Result<CanaryClient, Box<dyn Error>> {
+    connect_to(matches, &servers(matches)?).await
+}
+
+/// Connects to `servers` with the credentials given for --canary.
+async fn connect_to(matches: &ArgMatches, servers: &[&String]) -> Result<CanaryClient, Box<dyn Error>> {
+    let builder = match (matches.get_one::<String>("username"), matches.get_one::<String>("api_token")) {
+        (Some(username), _) => CanaryClient::builder_for_user(username.as_str(), password(matches, username)?),
+        (None, Some(api_token)) => CanaryClient::builder(api_token.as_str()),
+        (None, None) => return Err("--api_token or --username is required".into()),
+    };
+    connect_with(matches, builder, servers).await
+}
+
+async fn connect_with(matches: &ArgMatches, builder: CanaryClientBuilder, servers: &[&String]) -> Result<CanaryClient, Box<dyn Error>> {
+    let audit = AuditLog::open(matches.get_one::<String>("audit_log").map(String::as_str))?;
+    let retries = *matches.get_one::<u32>("retries").unwrap();
+    let client = servers
+        .iter()
+        .fold(builder, |builder, server| builder.server(server.as_str()))
+        .api_version(matches.get_one::<String>("api_version").unwrap().as_str())
+        .max_response_size(matches.get_one::<u64>("max_response_size").copied())
+        .compress_requests(matches.get_flag("compress_requests"))
+        .context_batch_size(*matches.get_one::<u64>("batch_size").unwrap() as usize)
+        .concurrency(*matches.get_one::<u64>("concurrency").unwrap() as usize)
+        .retries(retries)
+        .rate_limit(matches.get_one::<f64>("rate_limit").copied())
+        .max_requests(matches.get_one::<u64>("max_requests").copied())
+        .retry_delay(Duration::from_millis(*matches.get_one::<u64>("retry_delay").unwrap()))
+        .on_retry(move |_, error, attempt, delay| {
+            tracing::warn!("{}; retrying in {:.1}s (attempt {} of {}).", exit::chain_text(error), delay.as_secs_f64(), attempt, retries);
+        })
+        .audit_log(audit)
+        .connect()
+        .await?;
+    for server in client.skipped_servers() {
+        tracing::warn!("{} is unreachable, trying the next server.", server);
+    }
+    Ok(client)
+}
+
+/// Tag names, the historian that listed each and the per-tag columns of a
+/// CSV tags file.
+type Browsed<'a> = (Vec<String>, HashMap<String, &'a str>, HashMap<String, TagParams>);
+
+/// Browses every --historian scope, or the whole namespace without one,
+/// unless --tags_file lists the tags. With more than one historian, also
+/// returns which historian listed each tag. An empty browse is retried with
+/// backoff when --retry_on_empty is set, since a loaded historian can
+/// answer with no tags.
+async fn browse<'a>(client: &CanaryClient, matches: &'a ArgMatches) -> Result<Browsed<'a>, Box<dyn Error>> {
     let application = matches.get_one::<String>("application").unwrap();
     let timezone = matches.get_one::<String>("timezone").unwrap();
+    let historians: Vec<&str> = matches.get_many::<String>("historian").unwrap_or_default().map(String::as_str).collect();
+    let retries = *matches.get_one::<u32>("retry_on_empty").unwrap();
+    let path = matches.get_one::<String>("path").map(String::as_str);
+    let filter = TagFilter::from_matches(matches);
+    if let Some(source) = matches.get_one::<String>("tags_file") {
+        let (mut tags, params) = tag_list::read(source)?;
+        tags.retain(|tag| filter.matches(tag));
+        return Ok((limit_tags(tags, matches)?, HashMap::new(), params));
+    }
+    let scopes: Vec<Option<&str>> = if historians.is_empty() { vec![None] } else { historians.iter().copied().map(Some).collect() };
+    let mut tags = Vec::new();
+    let mut tag_historians = HashMap::new();
+    for historian in scopes {
+        let browse = BrowseRequest::builder()
+            .application(application)
+            .timezone(timezone)
+            .path(match (historian, path) {
+                (Some(historian), Some(path)) => format!("{}.{}", historian, path),
+                (Some(node), None) | (None, Some(node)) => node.to_string(),
+                (None, None) => String::new(),
+            })
+            .deep(!matches.get_flag("no_deep"))
+            .search(matches.get_one::<String>("search").unwrap())
+            .build();
+        let mut browsed = client.browse_tags(&browse).await?;
+        for attempt in 1..=retries {
+            if !browsed.is_empty() {
+                break;
+            }
+            let delay = RETRY_BASE_DELAY * 2u32.saturating_pow(attempt - 1).min(MAX_RETRY_DELAY_FACTOR);
+            tracing::warn!("browse returned no tags; retrying in {}s (attempt {} of {}).", delay.as_secs(), attempt, retries);
+            tokio::time::sleep(delay).await;
+            browsed = client.browse_tags(&browse).await?;
+        }
+        if !filter.is_empty() {
+            browsed.retain(|tag| filter.matches(tag));
+        }
+        if let (Some(historian), true) = (historian, historians.len() > 1) {
+            tag_historians.extend(browsed.iter().map(|tag| (tag.clone(), historian)));
+        }
+        tags.extend(browsed);
+    }
+    Ok((limit_tags(tags, matches)?, tag_historians, HashMap::new()))
+}
+
+/// Applies --max_tags: aborts when there are more tags, or keeps the first
+/// ones with --truncate_tags.
+fn limit_tags(mut tags: Vec<String>, matches: &ArgMatches) -> Result<Vec<String>, Box<dyn Error>> {
+    let Some(&max) = matches.get_one::<usize>("max_tags") else {
+        return Ok(tags);
+    };
+    if tags.len() > max {
+        if !matches.get_flag("truncate_tags") {
+            return Err(format!("found {} tags, more than --max_tags {}; narrow the browse or raise the limit", tags.len(), max).into());
+        }
+        tracing::warn!("found {} tags; keeping the first {} (--max_tags).", tags.len(), max);
+        tags.truncate(max);
+    }
+    Ok(tags)
+}
+
+async fn run_browse(matches: &ArgMatches) -> Result<(), CliError> {
+    let client = connect(matches).await?;
+    closing(&client, async {
+        let (tags, _, _) = browse(&client, matches).await?;
+        client.close().await?;
+        if tags.is_empty() {
+            return Err(CliError::NoTags("no tags found".to_string()));
+        }
+        for tag in tags {
+            println!("{}", tag);
+        }
+        Ok(())
+    })
+    .await
+}
+
+async fn run_near_duplicates(matches: &ArgMatches) -> Result<(), CliError> {
+    let client = connect(matches).await?;
+    closing(&client, async {
+        let (tags, _, _) = browse(&client, matches).await?;
+        client.close().await?;
+        if tags.is_empty() {
+            return Err(CliError::NoTags("no tags found".to_string()));
+        }
+        let pairs = similar::near_duplicates(&tags, *matches.get_one::<usize>("max_distance").unwrap());
+        for pair in &pairs {
+            let folder = if pair.folder.is_empty() { String::new() } else { format!("{}.", pair.folder) };
+            println!("{}{} ~ {}{} (distance {})", folder, pair.first, folder, pair.second, pair.distance);
+        }
+        println!("Summary: {} near-duplicate pairs among {} tags.", pairs.len(), tags.len());
+        Ok(())
+    })
+    .await
+}
+
+async fn run_tags(matches: &ArgMatches) -> Result<(), CliError> {
     let output_format = matches.get_one::<String>("output_format").unwrap();
     let output_file = matches.get_one::<String>("output_file").unwrap();
+    if output_format == "sqlite" {
+        return Err("sqlite output holds tag context and data; use export or data to write it".into());
+    }
+    let mut write_options = write_options(matches)?;
+    let client = connect(matches).await?;
+    closing(&client, async {
+        write_options.metadata = lineage(matches, &client, &BROWSE_QUERY);
+        let (mut tags, _, _) = browse(&client, matches).await?;
+        client.close().await?;
+        if tags.is_empty() {
+            return Err(CliError::NoTags("no tags found".to_string()));
+        }
+        let browsed = tags.len();
+        let mut seen = HashSet::new();
+        tags.retain(|tag| seen.insert(tag.clone()));
+
+        match output_format.as_str() {
+            "csv" => output::save_tags_to_csv(&tags, output_file, write_options)?,
+            "txt" => output::save_tags_to_txt(&tags, output_file, write_options)?,
+            "json" => output::save_tags_to_json(&tags, output_file, write_options)?,
+            "ndjson" => output::save_tags_to_ndjson(&tags, output_file, write_options)?,
+            "xlsx" => output::save_tags_to_xlsx(&tags, output_file, write_options)?,
+            "arrow" => output::save_tags_to_arrow(&tags, output_file, write_options)?,
+            other => return Err(format!("unsupported output format: {}", other).into()),
+        }
+        report(output_file, format_args!("Tag names saved to {} in {} format.", output_name(output_file), output_format));
+        report(output_file, format_args!("Summary: {} tags browsed ({} duplicate names), {} names written.", browsed, browsed - tags.len(), tags.len()));
+        Ok(())
+    })
+    .await
+}
 
-    let client = Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()?;
+async fn run_context(matches: &ArgMatches) -> Result<(), CliError> {
+    let client = connect(matches).await?;
+    closing(&client, async {
+        let tags: Vec<String> = matches.get_many::<String>("tags").unwrap().cloned().collect();
+        let data = client.get_tag_context(&tags).await?;
+        client.close().await?;
+        println!("{}", serde_json::to_string_pretty(&data)?);
+        Ok(())
+    })
+    .await
+}
 
-    let tags = get_tags(&client, canary, api_version, api_token, application, timezone).await?;
-    if !tags.is_empty() {
-        let tag_context_data = get_tag_context(&client, canary, api_version, api_token, tags).await?;
+/// The API has no reverse lookup, so this reads the context of every
+/// browsed tag and keeps the ones with a requested ID.
+async fn run_lookup(matches: &ArgMatches) -> Result<(), CliError> {
+    let ids: Vec<&String> = matches.get_many::<String>("historian_item_ids").unwrap().collect();
+    let client = connect(matches).await?;
+    closing(&client, async {
+        let (tags, _, _) = browse(&client, matches).await?;
+        let data = if tags.is_empty() { Vec::new() } else { client.get_tag_context(&tags).await? };
+        client.close().await?;
+        let found: Vec<&TagContext> = data.iter().filter(|item| item.details().historian_item_id().is_some_and(|id| ids.iter().any(|wanted| *wanted == id))).collect();
 
-        match output_format.as_str() {
-            "csv" => save_to_csv(&tag_context_data, output_file)?,
-            "txt" => save_to_txt(&tag_context_data, output_file)?,
-            "json" => save_to_json(&tag_context_data, output_file)?,
-            _ => unreachable!(),
+        for id in &ids {
+            if !found.iter().any(|item| item.details().historian_item_id() == Some(id.as_str())) {
+                tracing::warn!("no tag has historian item ID {}.", id);
+            }
+        }
+        if found.is_empty() {
+            return Err(CliError::NoTags(format!("none of the {} historian item IDs belong to a tag of {}", ids.len(), client.server())));
+        }
+        println!("{}", serde_json::to_string_pretty(&found)?);
+        Ok(())
+    })
+    .await
+}
+
+async fn run_data(matches: &ArgMatches) -> Result<(), CliError> {
+    let output_format = matches.get_one::<String>("output_format").unwrap();
+    let output_file = matches.get_one::<String>("output_file").unwrap();
+    let aggregate = matches.get_one::<String>("aggregate");
+    let mut request = TagDataRequest::builder()
+        .tags(matches.get_many::<String>("tags").unwrap())
+        .start_time(matches.get_one::<String>("start_time").unwrap())
+        .end_time(matches.get_one::<String>("end_time").unwrap())
+        .include_bounds(matches.get_one::<String>("bounds").unwrap() == "outside");
+    if let (Some(name), Some(interval)) = (aggregate, matches.get_one::<String>("aggregate_interval")) {
+        request = request.aggregate(name, interval);
+    }
+    let probe = request.clone().max_size(matches.get_one::<u32>("max_size").copied().unwrap_or(estimate::PROBE_SIZE)).build();
+    if let Some(max_size) = matches.get_one::<u32>("max_size") {
+        request = request.max_size(*max_size);
+    }
+    let request = request.build();
+    let mut write_options = write_options(matches)?;
+    let uncompressed = write_options.compression.map(|_| write_options.uncompressed.clone());
+
+    let client = connect(matches).await?;
+    closing(&client, async {
+        write_options.metadata = lineage(matches, &client, &DATA_QUERY);
+        if matches.get_flag("estimate") && !confirm_data_read(&client, &probe, matches, &write_options).await? {
+            client.close().await?;
+            report(output_file, format_args!("Cancelled; no data was read."));
+            return Ok(());
+        }
+        let data = client.get_tag_data(&request).await?;
+        let mut qualities = QualityTable::default();
+        if matches.get_flag("refresh_qualities") {
+            let codes: BTreeSet<u32> = data.iter().flat_map(|tag| tag.values().iter().filter_map(TagValue::quality)).collect();
+            if !codes.is_empty() {
+                qualities.extend(client.get_qualities(&codes.into_iter().collect::<Vec<_>>()).await?);
+            }
+        }
+        client.close().await?;
+        if data.is_empty() {
+            return Err(CliError::NoTags(format!("none of the {} requested tags returned data", request.tags().len())));
+        }
+        let records: Vec<DataRecord> = data
+            .iter()
+            .flat_map(|tag| tag.values().iter().map(|sample| DataRecord::new(tag.tag_name(), sample, aggregate.map(String::as_str)).with_quality_name(&qualities)))
+            .collect();
+
+        save_data(&records, output_format, output_file, write_options, matches.get_flag("auto_migrate"))?;
+
+        report(output_file, format_args!("Data saved to {} in {} format.", output_name(output_file), output_format));
+        report(output_file, format_args!("Summary: {} tags requested, {} returned data, {} samples written{}.", request.tags().len(), data.len(), records.len(), output_size(output_file, uncompressed.as_ref())?));
+        report(output_file, format_args!("Run ID: {}.", client.run_id()));
+        if let Some(clickhouse_url) = matches.get_one::<String>("clickhouse_url") {
+            let table = matches.get_one::<String>("clickhouse_table").unwrap();
+            let batches = clickhouse::insert_data(&Client::new(), clickhouse_url, table, &records, *matches.get_one::<usize>("clickhouse_batch_size").unwrap()).await?;
+            report(output_file, format_args!("Inserted {} samples into ClickHouse table {} in {} batches.", records.len(), table, batches));
         }
+        if let Some(questdb_addr) = matches.get_one::<String>("questdb_addr") {
+            let table = matches.get_one::<String>("questdb_table").unwrap();
+            let lines = questdb::send(questdb_addr, table, &records).await?;
+            report(output_file, format_args!("Sent {} samples to QuestDB table {}.", lines, table));
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn save_data(records: &[DataRecord], output_format: &str, output_file: &str, write_options: WriteOptions, auto_migrate: bool) -> Result<(), Box<dyn Error>> {
+    match output_format {
+        "csv" => output::save_data_to_csv(records, output_file, write_options),
+        "txt" => output::save_data_to_txt(records, output_file, write_options),
+        "json" => output::save_data_to_json(records, output_file, write_options),
+        "ndjson" => output::save_data_to_ndjson(records, output_file, write_options),
+        "xlsx" => output::save_data_to_xlsx(records, output_file, write_options),
+        "arrow" => output::save_data_to_arrow(records, output_file, write_options),
+        "sqlite" => sqlite::save_data_to_sqlite(records, output_file, write_options.max_bytes, auto_migrate),
+        other => Err(format!("unsupported output format: {}", other).into()),
+    }
+}
+
+/// Reads a first page of `probe`, prints the projected size of the whole
+/// read and asks whether to go ahead, unless --yes answers for the user.
+/// The output size is measured by writing the probed samples to a
+/// temporary file in the requested format.
+async fn confirm_data_read(client: &CanaryClient, probe: &TagDataRequest, matches: &ArgMatches, write_options: &WriteOptions) -> Result<bool, Box<dyn Error>> {
+    let range = estimate::range(probe.start_time(), probe.end_time(), chrono::Utc::now())?;
+    let started = std::time::Instant::now();
+    let page = client.get_tag_data_page(probe).await?;
+    let elapsed = started.elapsed();
+
+    let output_format = matches.get_one::<String>("output_format").unwrap();
+    let qualities = QualityTable::default();
+    let records: Vec<DataRecord> = page.data().iter().flat_map(|tag| tag.values().iter().map(|sample| DataRecord::new(tag.tag_name(), sample, probe.aggregate_name()).with_quality_name(&qualities))).collect();
+    let sample_file = std::env::temp_dir().join(format!("canary-context-estimate-{}.{}", std::process::id(), output_format));
+    let written = save_data(&records, output_format, &sample_file.to_string_lossy(), WriteOptions { max_bytes: None, uncompressed: ByteCount::default(), ..write_options.clone() }, false).and_then(|()| Ok(std::fs::metadata(&sample_file)?.len()));
+    let _ = std::fs::remove_file(&sample_file);
+    let estimate = estimate::estimate(&page, probe.max_size().unwrap_or(estimate::PROBE_SIZE), range, written?, elapsed)?;
+
+    report(matches.get_one::<String>("output_file").unwrap(), format_args!("Estimate for {} tags: {}.", probe.tags().len(), estimate));
+    if matches.get_flag("yes") {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err("--estimate asks before reading when run from a terminal; add --yes to read without asking".into());
+    }
+    eprint!("Read and export this data? [y/N] ");
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
 
-        println!("Data saved to {} in {} format.", output_file, output_format);
+/// Reads the range from both servers in full before comparing, so memory
+/// grows with the number of samples.
+async fn run_compare_data(matches: &ArgMatches) -> Result<(), CliError> {
+    let mut request = TagDataRequest::builder()
+        .tags(matches.get_many::<String>("tags").unwrap())
+        .start_time(matches.get_one::<String>("start_time").unwrap())
+        .end_time(matches.get_one::<String>("end_time").unwrap());
+    if let Some(max_size) = matches.get_one::<u32>("max_size") {
+        request = request.max_size(*max_size);
+    }
+    let request = request.build();
+    let other_servers: Vec<&String> = matches.get_many::<String>("other_canary").unwrap().collect();
+
+    let first = connect(matches).await?;
+    closing(&first, async {
+        let second = match matches.get_one::<String>("other_api_token") {
+            Some(api_token) => connect_with(matches, CanaryClient::builder(api_token.as_str()), &other_servers).await?,
+            None => connect_to(matches, &other_servers).await?,
+        };
+        closing(&second, async {
+            let (first_data, second_data) = tokio::try_join!(first.get_tag_data(&request), second.get_tag_data(&request))?;
+            first.close().await?;
+            second.close().await?;
+            if first_data.is_empty() && second_data.is_empty() {
+                return Err(CliError::NoTags(format!("none of the {} requested tags returned data on either server", request.tags().len())));
+            }
+
+            let (windows, mismatches) = compare::compare(&first_data, &second_data, *matches.get_one::<u64>("window").unwrap())?;
+            println!("Comparing {} (first) with {} (second).", first.server(), second.server());
+            for mismatch in &mismatches {
+                println!("{}", mismatch);
+            }
+            if !mismatches.is_empty() {
+                println!("Comparison failed: {} of {} windows differ.", mismatches.len(), windows);
+                return Err(CliError::Mismatches(mismatches.len()));
+            }
+            println!("Comparison passed: {} windows match.", windows);
+            Ok(())
+        })
+        .await
+    })
+    .await
+}
+
+/// Status lines go to stderr so stdout carries nothing but samples. The
+/// session is revoked on the way out, including after Ctrl+C.
+async fn run_live(matches: &ArgMatches) -> Result<(), CliError> {
+    let tags: Vec<String> = matches.get_many::<String>("tags").unwrap().cloned().collect();
+    let interval = Duration::from_millis(*matches.get_one::<u64>("poll_interval").unwrap());
+    let max_polls = matches.get_one::<u64>("polls").copied();
+    let mut out: Box<dyn Write> = match matches.get_one::<String>("output_file") {
+        Some(path) if path != output::STDOUT => Box::new(BufWriter::new(File::options().create(true).append(true).open(path)?)),
+        _ => Box::new(io::stdout().lock()),
+    };
+
+    let client = connect(matches).await?;
+    closing(&client, async {
+        let mut session = client.open_live_session(&tags).await?;
+        tracing::info!("Live session open for {} tags. Press Ctrl+C to stop.", tags.len());
+
+        let ctrl_c = tokio::signal::ctrl_c();
+        tokio::pin!(ctrl_c);
+        let (mut polls, mut samples) = (0u64, 0usize);
+        let result: Result<(), Box<dyn Error>> = loop {
+            let data = tokio::select! {
+                _ = &mut ctrl_c => break Ok(()),
+                data = client.poll_live_session(&mut session) => data,
+            };
+            let data = match data {
+                Ok(data) => data,
+                Err(e) => break Err(e),
+            };
+            let records: Vec<DataRecord> = data.iter().flat_map(|tag| tag.values().iter().map(|sample| DataRecord::new(tag.tag_name(), sample, None))).collect();
+            if let Err(e) = output::write_ndjson(&records, &mut out) {
+                break Err(e);
+            }
+            polls += 1;
+            samples += records.len();
+            if max_polls.is_some_and(|max| polls >= max) {
+                break Ok(());
+            }
+            tokio::select! {
+                _ = &mut ctrl_c => break Ok(()),
+                _ = tokio::time::sleep(interval) => {}
+            }
+        };
+
+        let closed = client.close_live_session(session).await;
+        result?;
+        closed?;
+        client.close().await?;
+        tracing::info!("Summary: {} polls, {} samples written.", polls, samples);
+        tracing::info!("Run ID: {}.", client.run_id());
+        Ok(())
+    })
+    .await
+}
+
+/// Progress goes to stderr, and only when it is a terminal and --quiet is
+/// not given.
+fn progress_target(matches: &ArgMatches) -> ProgressDrawTarget {
+    if matches.get_flag("quiet") {
+        ProgressDrawTarget::hidden()
     } else {
-        println!("No tags found.");
+        ProgressDrawTarget::stderr()
+    }
+}
+
+/// Colors follow the NO_COLOR and CLICOLOR_FORCE conventions; without
+/// either, only a terminal gets them.
+fn use_color(matches: &ArgMatches) -> bool {
+    let set = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty() && value != "0");
+    if matches.get_flag("no_color") || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return false;
     }
+    set("CLICOLOR_FORCE") || io::stdout().is_terminal()
+}
 
+async fn run_revoke_token(matches: &ArgMatches) -> Result<(), CliError> {
+    let token = match matches.get_one::<String>("token").unwrap().as_str() {
+        "-" => io::read_to_string(io::stdin())?.trim().to_string(),
+        token => token.to_string(),
+    };
+    if token.is_empty() {
+        return Err("no token given on stdin".into());
+    }
+    // revokeUserToken is authorized by the token it revokes.
+    let client = connect_with(matches, CanaryClient::builder(String::new()), &servers(matches)?).await?;
+    client.revoke_user_token(&token).await?;
+    println!("Token revoked on {}.", client.server());
     Ok(())
 }
+
+async fn run_baseline(matches: &ArgMatches) -> Result<(), CliError> {
+    let (command, args) = matches.subcommand().ok_or("baseline requires a subcommand: write or check")?;
+    let client = connect(args).await?;
+    closing(&client, async {
+        let (tags, _, _) = browse(&client, args).await?;
+        let tag_context_data = if tags.is_empty() { Vec::new() } else { client.get_tag_context(&tags).await? };
+        client.close().await?;
+        let live = Baseline::from_contexts(client.server(), &tag_context_data);
+        let file = args.get_one::<String>("file").unwrap();
+        match command {
+            "write" => {
+                live.save(file)?;
+                println!("Baseline of {} tags saved to {}.", live.tags.len(), file);
+            }
+            "check" => {
+                let key = Field::from_column(args.get_one::<String>("key").unwrap()).unwrap();
+                let compare: Vec<Field> = match args.get_many::<String>("compare") {
+                    Some(columns) => columns.filter_map(|column| Field::from_column(column)).collect(),
+                    None => Field::ALL.into_iter().filter(|field| *field != key).collect(),
+                };
+                let deviations = Baseline::load(file)?.check(&live, key, &compare);
+                if args.get_one::<String>("diff_format").unwrap() == "json" {
+                    let report = serde_json::json!({
+                        "baseline": file,
+                        "server": live.server,
+                        "passed": deviations.is_empty(),
+                        "tags": live.tags.len(),
+                        "deviations": deviations.iter().map(|deviation| deviation.to_json()).collect::<Vec<_>>()
+                    });
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                    if !deviations.is_empty() {
+                        return Err(CliError::Deviations(deviations.len()));
+                    }
+                    return Ok(());
+                }
+                let color = use_color(args);
+                for deviation in &deviations {
+                    if color {
+                        println!("{}", deviation.colored());
+                    } else {
+                        println!("{}", deviation);
+                    }
+                }
+                if !deviations.is_empty() {
+                    println!("Baseline check failed: {} deviations from {}.", deviations.len(), file);
+                    return Err(CliError::Deviations(deviations.len()));
+                }
+                println!("Baseline check passed: {} tags match {}.", live.tags.len(), file);
+            }
+            _ => return Err("baseline requires a subcommand: write or check".into()),
+        }
+        Ok(())
+    })
+    .await
+}
+
+async fn run_export(matches: &ArgMatches) -> Result<(), CliError> {
+    let servers = servers(matches)?;
+    let output_format = matches.get_one::<String>("output_format").unwrap();
+    let txt_template = match matches.get_one::<String>("txt_template") {
+        Some(_) if output_format != "txt" => return Err("--txt_template only applies to --output_format txt".into()),
+        Some(path) => Some(Template::parse(&std::fs::read_to_string(path).map_err(|e| format!("cannot read template {}: {}", path, e))?)?),
+        None => None,
+    };
+    // With --git_commit the output file is placed inside the working tree.
+    let git_repo = matches.get_one::<String>("git_commit").map(Path::new);
+    let output_file = matches.get_one::<String>("output_file").unwrap();
+    if git_repo.is_some() && output_file == output::STDOUT {
+        return Err("--git_commit needs an output file to commit, not stdout".into());
+    }
+    let output_file = &match git_repo {
+        Some(repo) => repo.join(output_file).to_str().ok_or("output path is not valid UTF-8")?.to_string(),
+        None => output_file.clone(),
+    };
+
+    let mut write_options = write_options(matches)?;
+    let uncompressed = write_options.compression.map(|_| write_options.uncompressed.clone());
+
+    let retrieved_at = chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let client = connect(matches).await?;
+    closing(&client, async {
+        write_options.metadata = lineage(matches, &client, &BROWSE_QUERY);
+        let canary = client.server();
+        let spinner = ProgressBar::with_draw_target(None, progress_target(matches)).with_message("Browsing tags...");
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        let (tags, tag_historians, tag_params) = browse(&client, matches).await?;
+        spinner.finish_and_clear();
+        if !tags.is_empty() {
+            let browsed = tags.len();
+            let duplicates = browsed - tags.iter().collect::<HashSet<_>>().len();
+            let progress = ProgressBar::with_draw_target(Some(browsed as u64), progress_target(matches))
+                .with_style(ProgressStyle::with_template("Fetching context [{bar:30}] {pos}/{len} tags, {per_sec}, ETA {eta}")?.progress_chars("=> "));
+            // IDs are keyed on the primary server so failover does not change them.
+            let row_id = matches.get_one::<String>("row_id").map(|kind| if kind == "uuid" { RowId::Uuid } else { RowId::Hash });
+            // NDJSON is written batch by batch, so loaders can start before the export ends.
+            let mut ndjson = if output_format == "ndjson" { Some(output::NdjsonFile::create(output_file, &write_options)?) } else { None };
+            let mut tag_context_data = Vec::new();
+            // A batch the server failed is listed in the errors file and the
+            // export goes on, unless no batch succeeded at all. One that was not
+            // sent, e.g. past --max_requests, ends the export.
+            let mut batch_errors: HashMap<&str, String> = HashMap::new();
+            let mut first_error = None;
+            let mut batches = pin!(client.get_tag_context_batches(&tags));
+            while let Some((batch, contexts)) = batches.next().await {
+                progress.inc(batch.len() as u64);
+                let contexts = match contexts {
+                    Ok(contexts) => contexts,
+                    Err(e) if !e.is::<CallError>() => return Err(e.into()),
+                    Err(e) => {
+                        let message = exit::chain_text(e.as_ref());
+                        tracing::warn!("getTagContext failed for a batch of {} tags, starting with {}: {}", batch.len(), batch[0], message);
+                        batch_errors.extend(batch.iter().map(|tag| (tag.as_str(), message.clone())));
+                        first_error.get_or_insert(e);
+                        continue;
+                    }
+                };
+                if let Some(ndjson) = &mut ndjson {
+                    ndjson.write(&export_records(&contexts, &retrieved_at, &tag_historians, &tag_params, row_id, servers[0]))?;
+                }
+                tag_context_data.extend(contexts);
+            }
+            progress.finish_and_clear();
+            client.close().await?;
+            if let Some(e) = first_error.filter(|_| tag_context_data.is_empty()) {
+                return Err(e.into());
+            }
+
+            let returned: HashSet<&str> = tag_context_data.iter().map(TagContext::tag_name).collect();
+            let mut reported = HashSet::new();
+            let failed: Vec<(&str, &str)> = tags
+                .iter()
+                .filter(|tag| !returned.contains(tag.as_str()) && reported.insert(tag.as_str()))
+                .map(|tag| (tag.as_str(), batch_errors.get(tag.as_str()).map_or("no context returned", String::as_str)))
+                .collect();
+            if !failed.is_empty() {
+                let errors_file = matches.get_one::<PathBuf>("errors_file").cloned().unwrap_or_else(|| Path::new(output_file).with_file_name(CONTEXT_ERRORS_FILE));
+                output::save_context_errors(&failed, &errors_file, write_options.clone())?;
+                if matches.get_flag("strict") {
+                    return Err(format!("{} tags returned no context (see {})", failed.len(), errors_file.display()).into());
+                }
+                tracing::warn!("{} tags returned no context; see {}.", failed.len(), errors_file.display());
+            }
+            let records = export_records(&tag_context_data, &retrieved_at, &tag_historians, &tag_params, row_id, servers[0]);
+
+            match output_format.as_str() {
+                "csv" => output::save_to_csv(&records, output_file, write_options)?,
+                "txt" => output::save_to_txt(&records, output_file, write_options, txt_template.as_ref())?,
+                "json" => output::save_to_json(&records, output_file, write_options)?,
+                "ndjson" => ndjson.take().ok_or("NDJSON output was not opened")?.finish()?,
+                "xlsx" => output::save_to_xlsx(&records, output_file, write_options)?,
+                "arrow" => output::save_to_arrow(&records, output_file, write_options)?,
+                "sqlite" => sqlite::save_to_sqlite(&records, output_file, write_options.max_bytes, matches.get_flag("auto_migrate"))?,
+                other => return Err(format!("unsupported output format: {}", other).into()),
+            }
+
+            report(output_file, format_args!("Data saved to {} in {} format.", output_name(output_file), output_format));
+            report(output_file, format_args!("Summary: {} tags browsed ({} duplicate names), {} records written{}.", browsed, duplicates, tag_context_data.len(), output_size(output_file, uncompressed.as_ref())?));
+            report(output_file, format_args!("Run ID: {}.", client.run_id()));
+            if servers.len() > 1 {
+                report(output_file, format_args!("Served by {}.", canary));
+            }
+            if let Some(clickhouse_url) = matches.get_one::<String>("clickhouse_url") {
+                let table = matches.get_one::<String>("clickhouse_table").unwrap();
+                let batches = clickhouse::insert(&Client::new(), clickhouse_url, table, &records, *matches.get_one::<usize>("clickhouse_batch_size").unwrap()).await?;
+                report(output_file, format_args!("Inserted {} rows into ClickHouse table {} in {} batches.", records.len(), table, batches));
+            }
+            if let Some(redis_url) = matches.get_one::<String>("redis_url") {
+                redis_cache::write(redis_url, matches.get_one::<String>("redis_prefix").unwrap(), *matches.get_one::<u64>("redis_ttl").unwrap(), &records).await?;
+                report(output_file, format_args!("Cached {} tags in Redis.", records.len()));
+            }
+            if let Some(nats_url) = matches.get_one::<String>("nats_url") {
+                let subject = matches.get_one::<String>("nats_subject").unwrap();
+                nats::publish(nats_url, subject, servers[0], &records).await?;
+                report(output_file, format_args!("Published {} tags to JetStream subject {}.", records.len(), subject));
+            }
+            if let Some(repo) = git_repo {
+                match git::commit_export(repo, Path::new(output_file), tag_context_data.len(), canary, output_format, txt_template.as_ref())? {
+                    Some(subject) => report(output_file, format_args!("Committed to {}: {}", repo.display(), subject)),
+                    None => report(output_file, format_args!("No inventory changes; nothing committed to {}.", repo.display())),
+                }
+            }
+        } else {
+            return Err(CliError::NoTags("no tags found".to_string()));
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+/// The output rows of an export, in the order of `contexts`.
+fn export_records<'a>(contexts: &'a [TagContext], retrieved_at: &'a str, tag_historians: &HashMap<String, &'a str>, tag_params: &'a HashMap<String, TagParams>, row_id: Option<RowId>, server: &str) -> Vec<Record<'a>> {
+    contexts
+        .iter()
+        .map(|context| Record::new(context, retrieved_at, tag_historians.get(context.tag_name()).copied()).with_params(tag_params.get(context.tag_name())))
+        .map(|record| match row_id {
+            Some(kind) => record.with_row_id(kind, server),
+            None => record,
+        })
+        .collect()
+}
+
+/// Value of `--name value` or `--name=value`, else of the environment
+/// variable, read before clap parses the command line because the profile it
+/// selects supplies clap's defaults.
+fn early_flag(args: &[String], name: &str, env: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    let prefix = format!("{}=", flag);
+    args.iter()
+        .enumerate()
+        .find_map(|(i, arg)| if *arg == flag { args.get(i + 1).cloned() } else { arg.strip_prefix(&prefix).map(String::from) })
+        .or_else(|| std::env::var(env).ok().filter(|value| !value.is_empty()))
+}
+
+fn cli_with_profile() -> Result<Command, Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let explicit = early_flag(&args, "config", "CANARY_CONFIG").map(PathBuf::from);
+    let config = match explicit.as_deref().map(|path| (path.to_path_buf(), true)).or_else(|| config::default_path().map(|path| (path, false))) {
+        Some((path, explicit)) => Config::load(&path, explicit)?,
+        None => Config::default(),
+    };
+    Ok(match config.profile(early_flag(&args, "profile", "CANARY_PROFILE").as_deref())? {
+        Some(profile) => {
+            // Only the subcommand being run takes the profile's subcommand defaults.
+            let subcommand = cli().ignore_errors(true).try_get_matches_from(&args).ok().and_then(|matches| matches.subcommand_name().map(String::from));
+            profile.apply(cli(), subcommand.as_deref())?
+        }
+        None => cli(),
+    })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let command = match cli_with_profile() {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let matches = match command.after_help(exit::CODES).try_get_matches() {
+        Ok(matches) => matches,
+        Err(e) => {
+            let _ = e.print();
+            return if e.use_stderr() { ExitCode::from(exit::USAGE) } else { ExitCode::SUCCESS };
+        }
+    };
+    logging::init(&matches);
+    let Some((command, args)) = matches.subcommand() else {
+        unreachable!("clap requires a subcommand")
+    };
+    let result = match command {
+        "browse" => run_browse(args).await,
+        "tags" => run_tags(args).await,
+        "near_duplicates" => run_near_duplicates(args).await,
+        "context" => run_context(args).await,
+        "lookup" => run_lookup(args).await,
+        "export" => run_export(args).await,
+        "data" => run_data(args).await,
+        "compare_data" => run_compare_data(args).await,
+        "live" => run_live(args).await,
+        "baseline" => run_baseline(args).await,
+        "revoke_token" => run_revoke_token(args).await,
+        "examples" => examples::print(cli, args).map_err(CliError::from),
+        "features" => {
+            features::print();
+            Ok(())
+        }
+        "self_test" => self_test::run(cli).await.map_err(CliError::from),
+        "version" => {
+            println!("canary-context {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        _ => unreachable!("clap only accepts known subcommands"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!("{}", exit::chain_text(&e));
+            if let Some(hint) = hint::for_error(&e, args.get_one::<String>("api_version").unwrap()) {
+                tracing::info!("Hint: {}", hint);
+            }
+            e.code()
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use crate::output::Record;
+use async_nats::jetstream::context::Publish;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+/// Publishes every record to a JetStream subject as the JSON export's row
+/// object. Each message carries a `Nats-Msg-Id` derived from the server, tag
+/// and run timestamp, so the stream drops duplicates when a run is retried
+/// within its dedup window. All messages are sent before the acks are
+/// awaited; the first failed ack fails the export.
+pub async fn publish(url: &str, subject: &str, server: &str, records: &[Record<'_>]) -> Result<(), Box<dyn Error>> {
+    let client = async_nats::connect(url).await?;
+    let jetstream = async_nats::jetstream::new(client);
+
+    let mut acks = Vec::with_capacity(records.len());
+    for record in records {
+        let id = message_id(server, record);
+        let publish = Publish::build().message_id(id).payload(serde_json::to_vec(record)?.into());
+        acks.push(jetstream.send_publish(subject.to_string(), publish).await?);
+    }
+    for ack in acks {
+        ack.await?;
+    }
+    Ok(())
+}
+
+fn message_id(server: &str, record: &Record) -> String {
+    let key = format!("{}\n{}\n{}", server.trim_end_matches('/'), record.tag_name, record.retrieved_at);
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
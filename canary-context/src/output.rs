@@ -0,0 +1,842 @@
+use crate::encoding::{EncodedWriter, Encoding};
+use crate::tag_list::TagParams;
+use crate::template::Template;
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, TimestampMicrosecondArray, UInt32Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use flate2::write::GzEncoder;
+use canary_context::quality::QualityTable;
+use chrono::{Datelike, Timelike};
+use canary_context::response::{TagContext, TagDetails, TagValue};
+use rust_xlsxwriter::{Color, ExcelDateTime, Format, FormatBorder, Workbook};
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use uuid::Uuid;
+
+/// Output file name that writes to stdout instead.
+pub const STDOUT: &str = "-";
+
+/// Values of --output_format.
+pub const FORMATS: [&str; 7] = ["csv", "txt", "json", "ndjson", "xlsx", "arrow", "sqlite"];
+/// Written in the output encoding, so it becomes that encoding's BOM.
+const BOM: &str = "\u{FEFF}";
+/// Last data row of a worksheet, below the header row.
+const XLSX_MAX_ROW: u32 = 1_048_575;
+/// Values of --locale with the Excel layout each writes xlsx timestamps in.
+pub const LOCALES: [(&str, &str); 8] = [
+    ("de-DE", "dd.mm.yyyy hh:mm:ss"),
+    ("en-GB", "dd/mm/yyyy hh:mm:ss"),
+    ("en-US", "mm/dd/yyyy hh:mm:ss"),
+    ("es-ES", "dd/mm/yyyy hh:mm:ss"),
+    ("fr-FR", "dd/mm/yyyy hh:mm:ss"),
+    ("it-IT", "dd/mm/yyyy hh:mm:ss"),
+    ("nl-NL", "dd-mm-yyyy hh:mm:ss"),
+    ("pl-PL", "dd.mm.yyyy hh:mm:ss"),
+];
+/// Columns that hold a Canary timestamp or the run's start.
+const TIMESTAMP_COLUMNS: [&str; 4] = ["oldest_time_stamp", "latest_time_stamp", "retrieved_at", "timestamp"];
+
+/// One output row: a tag's context plus the columns the CLI adds to it.
+/// Optional columns are only written when at least one row has a value.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record<'a> {
+    pub tag_name: Cow<'a, str>,
+    pub tag_context: &'a TagDetails,
+    /// When the run started (UTC), the same for every row of an export.
+    pub retrieved_at: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub historian: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row_id: Option<String>,
+    /// Columns given for the tag in a CSV tags file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<&'a str>,
+}
+
+impl<'a> Record<'a> {
+    /// The tag name is normalized to NFC so the same name is written with
+    /// the same bytes no matter how the server composed it.
+    pub fn new(context: &'a TagContext, retrieved_at: &'a str, historian: Option<&'a str>) -> Self {
+        Record { tag_name: nfc(context.tag_name()), tag_context: context.details(), retrieved_at, historian, row_id: None, alias: None, dataset: None, unit: None }
+    }
+
+    pub fn with_params(mut self, params: Option<&'a TagParams>) -> Self {
+        if let Some(params) = params {
+            self.alias = params.alias.as_deref();
+            self.dataset = params.dataset.as_deref();
+            self.unit = params.unit.as_deref();
+        }
+        self
+    }
+
+    pub fn with_row_id(mut self, kind: RowId, server: &str) -> Self {
+        self.row_id = Some(kind.generate(server, &self.tag_name));
+        self
+    }
+}
+
+/// Stable row identifiers derived from the server and tag name, so the same
+/// tag gets the same ID in every export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowId {
+    /// Hex SHA-256 of the server and tag name.
+    Hash,
+    /// Name-based (v5) UUID of the server and tag name.
+    Uuid,
+}
+
+impl RowId {
+    pub fn generate(self, server: &str, tag_name: &str) -> String {
+        let key = format!("{}\n{}", server.trim_end_matches('/'), tag_name);
+        match self {
+            RowId::Hash => format!("{:x}", Sha256::digest(key.as_bytes())),
+            RowId::Uuid => Uuid::new_v5(&Uuid::NAMESPACE_URL, key.as_bytes()).to_string(),
+        }
+    }
+}
+
+/// One sample of a `data` export.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataRecord<'a> {
+    pub tag_name: Cow<'a, str>,
+    #[serde(flatten)]
+    pub sample: &'a TagValue,
+    /// Aggregate the value was processed with; `None` for raw samples.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<&'a str>,
+    /// Readable name of the sample's quality code, written next to it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality_name: Option<Cow<'a, str>>,
+}
+
+impl<'a> DataRecord<'a> {
+    pub fn new(tag_name: &'a str, sample: &'a TagValue, aggregate: Option<&'a str>) -> Self {
+        DataRecord { tag_name: nfc(tag_name), sample, aggregate, quality_name: None }
+    }
+
+    pub fn with_quality_name(mut self, qualities: &'a QualityTable) -> Self {
+        self.quality_name = self.sample.quality().map(|code| qualities.name(code));
+        self
+    }
+}
+
+/// Optional columns of a `data` export, written when any record has them.
+struct DataColumns {
+    quality_name: bool,
+    aggregate: bool,
+}
+
+impl DataColumns {
+    fn of(data: &[DataRecord]) -> Self {
+        DataColumns { quality_name: data.iter().any(|record| record.quality_name.is_some()), aggregate: data.iter().any(|record| record.aggregate.is_some()) }
+    }
+
+    fn header(&self) -> Vec<&'static str> {
+        let mut header = vec!["tag_name", "timestamp", "value", "quality"];
+        if self.quality_name {
+            header.push("quality_name");
+        }
+        if self.aggregate {
+            header.push("aggregate");
+        }
+        header
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    /// Prefix formula-like CSV cells so spreadsheets show them as text.
+    pub escape_formulas: bool,
+    /// Start CSV and TXT files with a byte order mark.
+    pub bom: bool,
+    /// Write JSON on a single line instead of pretty-printed.
+    pub compact_json: bool,
+    /// Encoding of CSV and TXT files; JSON is always UTF-8.
+    pub encoding: Encoding,
+    /// Fail once the file would grow beyond this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Compress the file; the size limit applies to the compressed bytes.
+    pub compression: Option<Compression>,
+    /// Counts the bytes written before compression; clones share the count.
+    pub uncompressed: ByteCount,
+    /// Lineage key-value pairs stored in the schema of Arrow files.
+    pub metadata: HashMap<String, String>,
+    /// Excel number format of xlsx timestamps, from --locale; without one
+    /// they are written as text.
+    pub date_format: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ByteCount(Arc<AtomicU64>);
+
+impl ByteCount {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub const NAMES: [&'static str; 2] = ["gzip", "zstd"];
+
+    /// The compression a file name's extension asks for: `.gz` or `.zst`.
+    pub fn from_extension(filename: &str) -> Option<Self> {
+        match Path::new(filename).extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Some(Compression::Gzip),
+            Some("zst") => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Columns of a tabular export. The optional ones are only written when
+/// at least one record has a value for them.
+struct Columns {
+    historian: bool,
+    row_id: bool,
+    alias: bool,
+    dataset: bool,
+    unit: bool,
+}
+
+impl Columns {
+    fn of(data: &[Record]) -> Self {
+        Columns {
+            historian: data.iter().any(|record| record.historian.is_some()),
+            row_id: data.iter().any(|record| record.row_id.is_some()),
+            alias: data.iter().any(|record| record.alias.is_some()),
+            dataset: data.iter().any(|record| record.dataset.is_some()),
+            unit: data.iter().any(|record| record.unit.is_some()),
+        }
+    }
+
+    fn header(&self) -> Vec<&'static str> {
+        let mut header = vec!["tag_name", "historian_item_id", "source_item_id", "oldest_time_stamp", "latest_time_stamp", "retrieved_at"];
+        if self.historian {
+            header.push("historian");
+        }
+        if self.row_id {
+            header.push("row_id");
+        }
+        for (present, name) in [(self.alias, "alias"), (self.dataset, "dataset"), (self.unit, "unit")] {
+            if present {
+                header.push(name);
+            }
+        }
+        header
+    }
+
+    fn row<'r>(&self, record: &'r Record) -> Vec<&'r str> {
+        let item = record.tag_context;
+        let mut row = vec![
+            record.tag_name.as_ref(),
+            item.historian_item_id().unwrap_or(""),
+            item.source_item_id().unwrap_or(""),
+            item.oldest_time_stamp(),
+            item.latest_time_stamp(),
+            record.retrieved_at,
+        ];
+        if self.historian {
+            row.push(record.historian.unwrap_or(""));
+        }
+        if self.row_id {
+            row.push(record.row_id.as_deref().unwrap_or(""));
+        }
+        for (present, value) in [(self.alias, record.alias), (self.dataset, record.dataset), (self.unit, record.unit)] {
+            if present {
+                row.push(value.unwrap_or(""));
+            }
+        }
+        row
+    }
+}
+
+pub fn save_to_csv(data: &[Record], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(create(filename, options.bom, options.encoding, &options)?);
+    let columns = Columns::of(data);
+    wtr.write_record(columns.header())?;
+
+    for record in data {
+        let row = columns.row(record);
+        if options.escape_formulas {
+            wtr.write_record(row.into_iter().map(escape_formula).collect::<Vec<_>>().iter().map(|cell| cell.as_bytes()))?;
+        } else {
+            wtr.write_record(row)?;
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A worksheet with the same columns as the CSV. Cells are always text,
+/// so formula escaping does not apply.
+pub fn save_to_xlsx(data: &[Record], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let columns = Columns::of(data);
+    let rows = data.iter().map(|record| columns.row(record).into_iter().map(|cell| Value::String(cell.to_string())).collect());
+    save_xlsx(filename, &columns.header(), rows, options)
+}
+
+pub fn save_tags_to_xlsx(tags: &[String], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    save_xlsx(filename, &["tag_name"], tags.iter().map(|tag| vec![Value::String(nfc(tag).into_owned())]), options)
+}
+
+/// Numeric and boolean values are written as Excel numbers and booleans.
+pub fn save_data_to_xlsx(data: &[DataRecord], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let columns = DataColumns::of(data);
+    let rows = data.iter().map(|record| {
+        let mut row = vec![
+            Value::String(record.tag_name.to_string()),
+            Value::String(record.sample.timestamp().to_string()),
+            record.sample.value().clone(),
+            record.sample.quality().map_or(Value::Null, Value::from),
+        ];
+        if columns.quality_name {
+            row.push(record.quality_name.as_deref().map_or(Value::Null, Value::from));
+        }
+        if columns.aggregate {
+            row.push(record.aggregate.map_or(Value::Null, Value::from));
+        }
+        row
+    });
+    save_xlsx(filename, &columns.header(), rows, options)
+}
+
+/// Writes one worksheet with a bold, frozen header row and columns sized to
+/// their contents. With a date format, timestamp columns hold Excel dates
+/// at the wall time of the UTC offset each timestamp was given in.
+fn save_xlsx(filename: &str, header: &[&str], rows: impl Iterator<Item = Vec<Value>>, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    let header_format = Format::new().set_bold().set_background_color(Color::RGB(0xD9E1F2)).set_border_bottom(FormatBorder::Thin);
+    let date_format = options.date_format.map(|layout| Format::new().set_num_format(layout));
+    let dates: Vec<bool> = header.iter().map(|name| TIMESTAMP_COLUMNS.contains(name)).collect();
+    for (column, name) in header.iter().enumerate() {
+        sheet.write_string_with_format(0, column as u16, *name, &header_format)?;
+    }
+    for (index, cells) in rows.enumerate() {
+        let row = u32::try_from(index + 1).ok().filter(|row| *row <= XLSX_MAX_ROW).ok_or_else(|| format!("xlsx output holds at most {} rows; use csv for larger exports", XLSX_MAX_ROW))?;
+        for (column, cell) in cells.into_iter().enumerate() {
+            let column = column as u16;
+            match cell {
+                Value::Null => {}
+                Value::Bool(value) => {
+                    sheet.write_boolean(row, column, value)?;
+                }
+                Value::Number(number) => match number.as_f64() {
+                    Some(value) => {
+                        sheet.write_number(row, column, value)?;
+                    }
+                    None => {
+                        sheet.write_string(row, column, number.to_string())?;
+                    }
+                },
+                Value::String(text) => match &date_format {
+                    Some(format) if dates[usize::from(column)] => {
+                        sheet.write_datetime_with_format(row, column, excel_date_time(&text)?, format)?;
+                    }
+                    _ => {
+                        sheet.write_string(row, column, text)?;
+                    }
+                },
+                other => {
+                    sheet.write_string(row, column, other.to_string())?;
+                }
+            }
+        }
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    sheet.autofit();
+
+    let mut file = create(filename, false, Encoding::Utf8, &options)?;
+    file.write_all(&workbook.save_to_buffer()?)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn excel_date_time(text: &str) -> Result<ExcelDateTime, Box<dyn Error>> {
+    let time = chrono::DateTime::parse_from_rfc3339(text).map_err(|e| format!("cannot read timestamp {:?}: {}", text, e))?.naive_local();
+    let second = f64::from(time.second()) + f64::from(time.nanosecond()) / 1e9;
+    Ok(ExcelDateTime::from_ymd(u16::try_from(time.year())?, time.month() as u8, time.day() as u8)?.and_hms(time.hour() as u16, time.minute() as u8, second)?)
+}
+
+/// An Arrow IPC (Feather v2) file with one record batch. Timestamps are
+/// UTC microseconds; the ID columns are nullable strings.
+pub fn save_to_arrow(data: &[Record], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let columns = Columns::of(data);
+    let mut fields = vec![
+        Field::new("tag_name", DataType::Utf8, false),
+        Field::new("historian_item_id", DataType::Utf8, true),
+        Field::new("source_item_id", DataType::Utf8, true),
+        Field::new("oldest_time_stamp", timestamp_type(), false),
+        Field::new("latest_time_stamp", timestamp_type(), false),
+        Field::new("retrieved_at", timestamp_type(), false),
+    ];
+    let mut arrays: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(data.iter().map(|record| record.tag_name.as_ref()))),
+        Arc::new(StringArray::from_iter(data.iter().map(|record| record.tag_context.historian_item_id()))),
+        Arc::new(StringArray::from_iter(data.iter().map(|record| record.tag_context.source_item_id()))),
+        timestamp_array(data.iter().map(|record| record.tag_context.oldest_time_stamp()))?,
+        timestamp_array(data.iter().map(|record| record.tag_context.latest_time_stamp()))?,
+        timestamp_array(data.iter().map(|record| record.retrieved_at))?,
+    ];
+    if columns.historian {
+        fields.push(Field::new("historian", DataType::Utf8, true));
+        arrays.push(Arc::new(StringArray::from_iter(data.iter().map(|record| record.historian))));
+    }
+    if columns.row_id {
+        fields.push(Field::new("row_id", DataType::Utf8, true));
+        arrays.push(Arc::new(StringArray::from_iter(data.iter().map(|record| record.row_id.as_deref()))));
+    }
+    let params = [
+        (columns.alias, "alias", StringArray::from_iter(data.iter().map(|record| record.alias))),
+        (columns.dataset, "dataset", StringArray::from_iter(data.iter().map(|record| record.dataset))),
+        (columns.unit, "unit", StringArray::from_iter(data.iter().map(|record| record.unit))),
+    ];
+    for (present, name, column) in params {
+        if present {
+            fields.push(Field::new(name, DataType::Utf8, true));
+            arrays.push(Arc::new(column));
+        }
+    }
+    save_arrow(filename, fields, arrays, options)
+}
+
+pub fn save_tags_to_arrow(tags: &[String], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let names: ArrayRef = Arc::new(StringArray::from_iter_values(tags.iter().map(|tag| nfc(tag))));
+    save_arrow(filename, vec![Field::new("tag_name", DataType::Utf8, false)], vec![names], options)
+}
+
+/// The value column is Float64 when every value is a number or null,
+/// Boolean when every value is a bool or null, and Utf8 otherwise.
+pub fn save_data_to_arrow(data: &[DataRecord], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let columns = DataColumns::of(data);
+    let values = data.iter().map(|record| record.sample.value());
+    let value: ArrayRef = if values.clone().all(|value| value.is_number() || value.is_null()) {
+        Arc::new(Float64Array::from_iter(values.map(Value::as_f64)))
+    } else if values.clone().all(|value| value.is_boolean() || value.is_null()) {
+        Arc::new(BooleanArray::from_iter(values.map(Value::as_bool)))
+    } else {
+        Arc::new(StringArray::from_iter(values.map(|value| (!value.is_null()).then(|| value_cell(value)))))
+    };
+    let mut fields = vec![
+        Field::new("tag_name", DataType::Utf8, false),
+        Field::new("timestamp", timestamp_type(), false),
+        Field::new("value", value.data_type().clone(), true),
+        Field::new("quality", DataType::UInt32, true),
+    ];
+    let mut arrays: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(data.iter().map(|record| record.tag_name.as_ref()))),
+        timestamp_array(data.iter().map(|record| record.sample.timestamp()))?,
+        value,
+        Arc::new(UInt32Array::from_iter(data.iter().map(|record| record.sample.quality()))),
+    ];
+    if columns.quality_name {
+        fields.push(Field::new("quality_name", DataType::Utf8, true));
+        arrays.push(Arc::new(StringArray::from_iter(data.iter().map(|record| record.quality_name.as_deref()))));
+    }
+    if columns.aggregate {
+        fields.push(Field::new("aggregate", DataType::Utf8, true));
+        arrays.push(Arc::new(StringArray::from_iter(data.iter().map(|record| record.aggregate))));
+    }
+    save_arrow(filename, fields, arrays, options)
+}
+
+fn save_arrow(filename: &str, fields: Vec<Field>, arrays: Vec<ArrayRef>, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let batch = RecordBatch::try_new(Arc::new(Schema::new_with_metadata(fields, options.metadata.clone())), arrays)?;
+    // Built in memory because the IPC writer flushes after every message,
+    // which would end a compressed stream early.
+    let mut writer = FileWriter::try_new(Vec::new(), &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    let mut file = create(filename, false, Encoding::Utf8, &options)?;
+    file.write_all(&writer.into_inner()?)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+}
+
+/// Canary timestamps carry their UTC offset; they are stored as UTC.
+fn timestamp_array<'t>(timestamps: impl Iterator<Item = &'t str>) -> Result<ArrayRef, Box<dyn Error>> {
+    let micros = timestamps
+        .map(|text| chrono::DateTime::parse_from_rfc3339(text).map(|time| time.timestamp_micros()).map_err(|e| format!("cannot read timestamp {:?}: {}", text, e)))
+        .collect::<Result<Vec<i64>, String>>()?;
+    Ok(Arc::new(TimestampMicrosecondArray::from(micros).with_timezone("UTC")))
+}
+
+pub fn save_tags_to_csv(tags: &[String], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(create(filename, options.bom, options.encoding, &options)?);
+    wtr.write_record(["tag_name"])?;
+    for tag in tags {
+        let tag = nfc(tag);
+        wtr.write_record([if options.escape_formulas { escape_formula(&tag) } else { Cow::Borrowed(tag.as_ref()) }.as_ref()])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// One tag name per line.
+pub fn save_tags_to_txt(tags: &[String], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let mut file = create(filename, options.bom, options.encoding, &options)?;
+    for tag in tags {
+        writeln!(file, "{}", escape_line_breaks(&nfc(tag)))?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// A JSON array of tag names.
+pub fn save_tags_to_json(tags: &[String], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let tags: Vec<Cow<str>> = tags.iter().map(|tag| nfc(tag)).collect();
+    let mut file = create(filename, false, Encoding::Utf8, &options)?;
+    if options.compact_json {
+        serde_json::to_writer(&mut file, &tags)?;
+    } else {
+        serde_json::to_writer_pretty(&mut file, &tags)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// One JSON string per line.
+pub fn save_tags_to_ndjson(tags: &[String], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let tags: Vec<Cow<str>> = tags.iter().map(|tag| nfc(tag)).collect();
+    write_ndjson(&tags, &mut create(filename, false, Encoding::Utf8, &options)?)
+}
+
+/// Writes the tags that could not be exported, one row per tag with the
+/// reason, next to the export itself. It is a CSV in the export's encoding
+/// and formula escaping; compression follows its own file name, and the
+/// output size limit does not apply to it.
+pub fn save_context_errors(errors: &[(&str, &str)], path: &Path, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let filename = path.to_str().ok_or("errors file path is not valid UTF-8")?;
+    let options = WriteOptions { max_bytes: None, compression: Compression::from_extension(filename), uncompressed: ByteCount::default(), ..options };
+    let mut wtr = csv::Writer::from_writer(create(filename, options.bom, options.encoding, &options)?);
+    wtr.write_record(["tag_name", "error"])?;
+    for (tag_name, error) in errors {
+        let tag_name = nfc(tag_name);
+        if options.escape_formulas {
+            wtr.write_record([escape_formula(&tag_name), escape_formula(error)].iter().map(|cell| cell.as_bytes()))?;
+        } else {
+            wtr.write_record([tag_name.as_ref(), error])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+pub fn save_to_txt(data: &[Record], filename: &str, options: WriteOptions, template: Option<&Template>) -> Result<(), Box<dyn Error>> {
+    let mut file = create(filename, options.bom, options.encoding, &options)?;
+
+    for record in data {
+        if let Some(template) = template {
+            file.write_all(template.render(record).as_bytes())?;
+            continue;
+        }
+        let item = record.tag_context;
+        writeln!(file, "TagName: {}", escape_line_breaks(&record.tag_name))?;
+        if let Some(historian) = record.historian {
+            writeln!(file, "  Historian: {}", historian)?;
+        }
+        writeln!(file, "  HistorianItemId: {}", item.historian_item_id().unwrap_or(""))?;
+        writeln!(file, "  SourceItemId: {}", item.source_item_id().unwrap_or(""))?;
+        writeln!(file, "  OldestTimeStamp: {}", item.oldest_time_stamp())?;
+        writeln!(file, "  LatestTimeStamp: {}", item.latest_time_stamp())?;
+        writeln!(file, "  RetrievedAt: {}", record.retrieved_at)?;
+        if let Some(row_id) = &record.row_id {
+            writeln!(file, "  RowId: {}", row_id)?;
+        }
+        for (label, value) in [("Alias", record.alias), ("Dataset", record.dataset), ("Unit", record.unit)] {
+            if let Some(value) = value {
+                writeln!(file, "  {}: {}", label, escape_line_breaks(value))?;
+            }
+        }
+        writeln!(file)?;
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+/// Numbers and booleans are written as JSON literals; only string values
+/// are formula-escaped, so negative numbers stay numeric.
+pub fn save_data_to_csv(data: &[DataRecord], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let columns = DataColumns::of(data);
+    let mut wtr = csv::Writer::from_writer(create(filename, options.bom, options.encoding, &options)?);
+    wtr.write_record(columns.header())?;
+
+    for record in data {
+        let tag_name = if options.escape_formulas { escape_formula(&record.tag_name) } else { Cow::Borrowed(record.tag_name.as_ref()) };
+        let value = value_cell(record.sample.value());
+        let value = if options.escape_formulas && record.sample.value().is_string() { Cow::Owned(escape_formula(&value).into_owned()) } else { value };
+        let quality = record.sample.quality().map(|quality| quality.to_string()).unwrap_or_default();
+        let mut row = vec![tag_name.as_ref(), record.sample.timestamp(), value.as_ref(), quality.as_str()];
+        if columns.quality_name {
+            row.push(record.quality_name.as_deref().unwrap_or(""));
+        }
+        if columns.aggregate {
+            row.push(record.aggregate.unwrap_or(""));
+        }
+        wtr.write_record(row)?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+pub fn save_data_to_txt(data: &[DataRecord], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let mut file = create(filename, options.bom, options.encoding, &options)?;
+
+    for record in data {
+        writeln!(file, "TagName: {}", escape_line_breaks(&record.tag_name))?;
+        writeln!(file, "  Timestamp: {}", record.sample.timestamp())?;
+        writeln!(file, "  Value: {}", escape_line_breaks(&value_cell(record.sample.value())))?;
+        writeln!(file, "  Quality: {}", record.sample.quality().map(|quality| quality.to_string()).unwrap_or_default())?;
+        if let Some(quality_name) = &record.quality_name {
+            writeln!(file, "  QualityName: {}", quality_name)?;
+        }
+        if let Some(aggregate) = record.aggregate {
+            writeln!(file, "  Aggregate: {}", aggregate)?;
+        }
+        writeln!(file)?;
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+/// Samples are written in the order the server returned them per tag, with
+/// tags in name order.
+pub fn save_data_to_json(data: &[DataRecord], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let mut file = create(filename, false, Encoding::Utf8, &options)?;
+    if options.compact_json {
+        serde_json::to_writer(&mut file, data)?;
+    } else {
+        serde_json::to_writer_pretty(&mut file, data)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// One sample per line in the order the server returned them per tag.
+pub fn save_data_to_ndjson(data: &[DataRecord], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    write_ndjson(data, &mut create(filename, false, Encoding::Utf8, &options)?)
+}
+
+/// Writes one JSON object per line and flushes, so a reader following the
+/// stream sees each batch as soon as it arrives.
+pub fn write_ndjson<T: Serialize, W: Write>(data: &[T], out: &mut W) -> Result<(), Box<dyn Error>> {
+    for record in data {
+        serde_json::to_writer(&mut *out, record)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn value_cell(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::String(text) => Cow::Borrowed(text),
+        Value::Null => Cow::Borrowed(""),
+        other => Cow::Owned(other.to_string()),
+    }
+}
+
+/// A file, or stdout for `-`.
+type Output = BufWriter<Limited<Box<dyn Write>>>;
+
+fn create(filename: &str, bom: bool, encoding: Encoding, options: &WriteOptions) -> Result<EncodedWriter<Counted<Compressed>>, Box<dyn Error>> {
+    let inner: Box<dyn Write> = if filename == STDOUT { Box::new(io::stdout().lock()) } else { Box::new(File::create(filename)?) };
+    let file = BufWriter::new(Limited { inner, remaining: options.max_bytes.unwrap_or(u64::MAX), limit: options.max_bytes });
+    let file = match options.compression {
+        None => Compressed::Plain(file),
+        Some(Compression::Gzip) => Compressed::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+        Some(Compression::Zstd) => Compressed::Zstd(zstd::Encoder::new(file, 0)?),
+    };
+    let mut file = EncodedWriter::new(Counted { inner: file, count: options.uncompressed.clone() }, encoding);
+    if bom {
+        file.write_all(BOM.as_bytes())?;
+    }
+    Ok(file)
+}
+
+/// The output, compressed or not. Flushing a compressed output completes
+/// the stream, so it must only be flushed once everything is written, which
+/// is what every writer here does; `sync` pushes out what was written so
+/// far and leaves the stream open.
+enum Compressed {
+    Plain(Output),
+    Gzip(GzEncoder<Output>),
+    Zstd(zstd::Encoder<'static, Output>),
+}
+
+impl Compressed {
+    fn sync(&mut self) -> io::Result<()> {
+        match self {
+            Compressed::Plain(file) => file.flush(),
+            Compressed::Gzip(encoder) => encoder.flush(),
+            Compressed::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl Write for Compressed {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Compressed::Plain(file) => file.write(buf),
+            Compressed::Gzip(encoder) => encoder.write(buf),
+            Compressed::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Compressed::Plain(file) => file.flush(),
+            Compressed::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()
+            }
+            Compressed::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()
+            }
+        }
+    }
+}
+
+/// Adds the bytes written through it to `count`.
+struct Counted<W: Write> {
+    inner: W,
+    count: ByteCount,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count.0.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Fails a write that would take the file past `limit` bytes.
+struct Limited<W: Write> {
+    inner: W,
+    remaining: u64,
+    limit: Option<u64>,
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::other(format!("output file would exceed the maximum output size of {} bytes", self.limit.unwrap_or_default())));
+        }
+        let written = self.inner.write(buf)?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn nfc(value: &str) -> Cow<'_, str> {
+    if is_nfc(value) {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(value.nfc().collect())
+    }
+}
+
+/// Prefixes cells that spreadsheet applications would evaluate as a
+/// formula with a single quote, so they are displayed as text instead.
+fn escape_formula(cell: &str) -> Cow<'_, str> {
+    if cell.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", cell))
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
+
+/// Keeps every TXT record field on one line, whatever the tag name contains.
+pub fn escape_line_breaks(value: &str) -> String {
+    value.replace('\r', "\\r").replace('\n', "\\n")
+}
+
+/// One record per line in the order the server returned them, so loaders
+/// can start on the first rows before the file is complete.
+pub fn save_to_ndjson(data: &[Record], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let mut file = NdjsonFile::create(filename, &options)?;
+    file.write(data)?;
+    file.finish()
+}
+
+/// An NDJSON file written batch by batch as the records are produced.
+pub struct NdjsonFile(EncodedWriter<Counted<Compressed>>);
+
+impl NdjsonFile {
+    pub fn create(filename: &str, options: &WriteOptions) -> Result<Self, Box<dyn Error>> {
+        Ok(NdjsonFile(create(filename, false, Encoding::Utf8, options)?))
+    }
+
+    /// Appends one line per record and pushes them out to the file, also
+    /// through a compressed stream, which stays open for the next batch.
+    pub fn write<T: Serialize>(&mut self, data: &[T]) -> Result<(), Box<dyn Error>> {
+        for record in data {
+            serde_json::to_writer(&mut self.0, record)?;
+            self.0.write_all(b"\n")?;
+        }
+        self.0.get_mut().inner.sync()?;
+        Ok(())
+    }
+
+    /// Completes the file; without it a compressed file is left truncated.
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// Rows are sorted by tag name and keys keep the `Record` field order, so
+/// two exports of the same inventory are byte-identical apart from
+/// `retrievedAt`.
+pub fn save_to_json(data: &[Record], filename: &str, options: WriteOptions) -> Result<(), Box<dyn Error>> {
+    let mut rows: Vec<&Record> = data.iter().collect();
+    rows.sort_by(|a, b| a.tag_name.cmp(&b.tag_name));
+    let mut file = create(filename, false, Encoding::Utf8, &options)?;
+    if options.compact_json {
+        serde_json::to_writer(&mut file, &rows)?;
+    } else {
+        serde_json::to_writer_pretty(&mut file, &rows)?;
+    }
+    file.flush()?;
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Names of Canary quality codes. Canary reports OPC DA qualities: the top
+//! two bits of the low byte give the status, the next four a sub-status and
+//! the last two the limit.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// Quality names by code: the built-in OPC DA names, overridden by any
+/// names the server reported with `CanaryClient::get_qualities`.
+///
+/// ```
+/// use canary_context::quality::QualityTable;
+///
+/// let mut qualities = QualityTable::default();
+/// assert_eq!(qualities.name(192), "Good");
+/// assert_eq!(qualities.name(24), "Bad: Comm Failure");
+/// qualities.extend([(24, "Bad - No Communication".to_string())]);
+/// assert_eq!(qualities.name(24), "Bad - No Communication");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct QualityTable {
+    names: BTreeMap<u32, String>,
+}
+
+impl QualityTable {
+    pub fn name(&self, code: u32) -> Cow<'_, str> {
+        match self.names.get(&code) {
+            Some(name) => Cow::Borrowed(name),
+            None => Cow::Owned(builtin_name(code)),
+        }
+    }
+
+    /// Replaces the names of the given codes.
+    pub fn extend(&mut self, names: impl IntoIterator<Item = (u32, String)>) {
+        self.names.extend(names);
+    }
+}
+
+fn builtin_name(code: u32) -> String {
+    if code > 0xFF {
+        return format!("Unknown ({})", code);
+    }
+    let sub_status = (code >> 2) & 0x0F;
+    let status = match code & 0xC0 {
+        0x00 => match sub_status {
+            0 => Some("Bad"),
+            1 => Some("Bad: Configuration Error"),
+            2 => Some("Bad: Not Connected"),
+            3 => Some("Bad: Device Failure"),
+            4 => Some("Bad: Sensor Failure"),
+            5 => Some("Bad: Last Known Value"),
+            6 => Some("Bad: Comm Failure"),
+            7 => Some("Bad: Out of Service"),
+            8 => Some("Bad: Waiting for Initial Data"),
+            _ => None,
+        },
+        0x40 => match sub_status {
+            0 => Some("Uncertain"),
+            1 => Some("Uncertain: Last Usable Value"),
+            4 => Some("Uncertain: Sensor Not Accurate"),
+            5 => Some("Uncertain: Engineering Units Exceeded"),
+            6 => Some("Uncertain: Sub-Normal"),
+            _ => None,
+        },
+        0xC0 => match sub_status {
+            0 => Some("Good"),
+            6 => Some("Good: Local Override"),
+            _ => None,
+        },
+        _ => None,
+    };
+    match (status, code & 0x03) {
+        (None, _) => format!("Unknown ({})", code),
+        (Some(status), 0) => status.to_string(),
+        (Some(status), 1) => format!("{} (Low Limited)", status),
+        (Some(status), 2) => format!("{} (High Limited)", status),
+        (Some(status), _) => format!("{} (Constant)", status),
+    }
+}
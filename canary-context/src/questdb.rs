@@ -0,0 +1,91 @@
+use crate::output::DataRecord;
+use serde_json::Value;
+use std::error::Error;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::net::TcpStream;
+
+/// Sends every sample to QuestDB as InfluxDB line protocol over TCP, one
+/// line per sample with the tag name (and aggregate, when set) as symbol
+/// columns. Numbers go to a `value` double column, strings to `value_text`
+/// and booleans to `value_bool`, since a QuestDB column holds one type.
+/// Samples without a value or quality are skipped. ILP over TCP sends no
+/// acknowledgements, so rows QuestDB rejects only show up in its log.
+/// Returns the number of lines sent.
+pub async fn send(addr: &str, table: &str, records: &[DataRecord<'_>]) -> Result<usize, Box<dyn Error>> {
+    if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+        return Err(format!("invalid QuestDB table name: {}", table).into());
+    }
+    let mut stream = BufWriter::new(TcpStream::connect(addr).await.map_err(|e| format!("cannot connect to QuestDB at {}: {}", addr, e))?);
+
+    let mut lines = 0;
+    for record in records {
+        if let Some(line) = line(table, record)? {
+            stream.write_all(line.as_bytes()).await?;
+            lines += 1;
+        }
+    }
+    stream.flush().await?;
+    stream.into_inner().shutdown().await?;
+    Ok(lines)
+}
+
+fn line(table: &str, record: &DataRecord) -> Result<Option<String>, Box<dyn Error>> {
+    let sample = record.sample;
+    let mut fields = Vec::new();
+    match sample.value() {
+        Value::Number(number) => fields.push(format!("value={}", number.as_f64().unwrap_or(f64::NAN))),
+        Value::Bool(value) => fields.push(format!("value_bool={}", if *value { 't' } else { 'f' })),
+        Value::String(text) => fields.push(format!("value_text={}", string_field(text))),
+        Value::Null => {}
+        other => fields.push(format!("value_text={}", string_field(&other.to_string()))),
+    }
+    if let Some(quality) = sample.quality() {
+        fields.push(format!("quality={}i", quality));
+    }
+    if let Some(name) = &record.quality_name {
+        fields.push(format!("quality_name={}", string_field(name)));
+    }
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let timestamp = chrono::DateTime::parse_from_rfc3339(sample.timestamp())
+        .ok()
+        .and_then(|time| time.timestamp_nanos_opt())
+        .ok_or_else(|| format!("cannot send the {} sample at {:?} to QuestDB: not an RFC 3339 timestamp", record.tag_name, sample.timestamp()))?;
+
+    let aggregate = record.aggregate.map(|aggregate| format!(",aggregate={}", symbol(aggregate))).unwrap_or_default();
+    Ok(Some(format!("{},tag_name={}{} {} {}\n", table, symbol(&record.tag_name), aggregate, fields.join(","), timestamp)))
+}
+
+/// A symbol value with the characters that would end it, line breaks
+/// included, backslash-escaped.
+fn symbol(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            ' ' | ',' | '=' | '\\' | '\n' | '\r' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A quoted string field value.
+fn string_field(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' | '\\' | '\n' | '\r' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,44 @@
+use crate::output::Record;
+use std::error::Error;
+
+const PIPELINE_SIZE: usize = 1000;
+
+/// Writes each record's context to a Redis hash at `{prefix}{tag_name}`
+/// with the CSV column names as fields, so consumers can look up the
+/// latest timestamps without calling Canary. Every hash expires after
+/// `ttl` seconds unless a later run refreshes it. Fields a record has no
+/// value for are removed rather than left over from an earlier run.
+pub async fn write(url: &str, prefix: &str, ttl: u64, records: &[Record<'_>]) -> Result<(), Box<dyn Error>> {
+    let client = redis::Client::open(url)?;
+    let mut connection = client.get_multiplexed_async_connection().await?;
+    let ttl = i64::try_from(ttl).map_err(|_| "--redis_ttl is too large")?;
+
+    for batch in records.chunks(PIPELINE_SIZE) {
+        let mut pipe = redis::pipe();
+        for record in batch {
+            let details = record.tag_context;
+            let key = format!("{}{}", prefix, record.tag_name);
+            let fields = [
+                ("historian_item_id", details.historian_item_id()),
+                ("source_item_id", details.source_item_id()),
+                ("oldest_time_stamp", Some(details.oldest_time_stamp())),
+                ("latest_time_stamp", Some(details.latest_time_stamp())),
+                ("retrieved_at", Some(record.retrieved_at)),
+                ("historian", record.historian),
+                ("row_id", record.row_id.as_deref()),
+                ("alias", record.alias),
+                ("dataset", record.dataset),
+                ("unit", record.unit),
+            ];
+            let present: Vec<(&str, &str)> = fields.iter().filter_map(|(field, value)| value.map(|value| (*field, value))).collect();
+            let absent: Vec<&str> = fields.iter().filter(|(_, value)| value.is_none()).map(|(field, _)| *field).collect();
+            pipe.cmd("HSET").arg(&key).arg(&present).ignore();
+            if !absent.is_empty() {
+                pipe.hdel(&key, absent).ignore();
+            }
+            pipe.expire(&key, ttl).ignore();
+        }
+        pipe.query_async::<()>(&mut connection).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use serde::Serialize;
+
+/// Parameters of a browseTags call. The API token is not part of the
+/// request; it is added by whoever sends it.
+///
+/// ```
+/// use canary_context::request::BrowseRequest;
+///
+/// let request = BrowseRequest::builder().path("Plant1").deep(true).search("temp").build();
+/// assert_eq!(request.path(), "Plant1");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowseRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    application: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    path: String,
+    deep: bool,
+    search: String,
+}
+
+impl BrowseRequest {
+    pub fn builder() -> BrowseRequestBuilder {
+        BrowseRequestBuilder::default()
+    }
+
+    pub fn application(&self) -> Option<&str> {
+        self.application.as_deref()
+    }
+
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn deep(&self) -> bool {
+        self.deep
+    }
+
+    pub fn search(&self) -> &str {
+        &self.search
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BrowseRequestBuilder {
+    request: BrowseRequest,
+}
+
+impl BrowseRequestBuilder {
+    /// Client application name reported to the server.
+    pub fn application(mut self, application: impl Into<String>) -> Self {
+        self.request.application = Some(application.into());
+        self
+    }
+
+    /// Windows time zone name used for timestamps in the response.
+    pub fn timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.request.timezone = Some(timezone.into());
+        self
+    }
+
+    /// Node to browse from; empty browses from the root.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.request.path = path.into();
+        self
+    }
+
+    /// Include tags in all nodes below `path`, not just direct children.
+    pub fn deep(mut self, deep: bool) -> Self {
+        self.request.deep = deep;
+        self
+    }
+
+    /// Server-side search filter applied to tag names.
+    pub fn search(mut self, search: impl Into<String>) -> Self {
+        self.request.search = search.into();
+        self
+    }
+
+    pub fn build(self) -> BrowseRequest {
+        self.request
+    }
+}
+
+/// Parameters of a getTagData call. Times are passed to the server as
+/// given, so both absolute timestamps and Canary relative times such as
+/// `Now-1Day` work.
+///
+/// ```
+/// use canary_context::request::TagDataRequest;
+///
+/// let request = TagDataRequest::builder().tag("Plant1.Line1.Temperature").start_time("Now-1Hour").end_time("Now").build();
+/// assert_eq!(request.tags(), ["Plant1.Line1.Temperature"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagDataRequest {
+    tags: Vec<String>,
+    start_time: String,
+    end_time: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregate_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregate_interval: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    include_bounds: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_size: Option<u32>,
+}
+
+impl TagDataRequest {
+    pub fn builder() -> TagDataRequestBuilder {
+        TagDataRequestBuilder::default()
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn start_time(&self) -> &str {
+        &self.start_time
+    }
+
+    pub fn end_time(&self) -> &str {
+        &self.end_time
+    }
+
+    pub fn aggregate_name(&self) -> Option<&str> {
+        self.aggregate_name.as_deref()
+    }
+
+    pub fn aggregate_interval(&self) -> Option<&str> {
+        self.aggregate_interval.as_deref()
+    }
+
+    pub fn include_bounds(&self) -> bool {
+        self.include_bounds
+    }
+
+    pub fn max_size(&self) -> Option<u32> {
+        self.max_size
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TagDataRequestBuilder {
+    request: TagDataRequest,
+}
+
+impl TagDataRequestBuilder {
+    /// Adds a tag to read.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.request.tags.push(tag.into());
+        self
+    }
+
+    /// Adds several tags to read.
+    pub fn tags<I: IntoIterator<Item = S>, S: Into<String>>(mut self, tags: I) -> Self {
+        self.request.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Start of the time range, inclusive.
+    pub fn start_time(mut self, start_time: impl Into<String>) -> Self {
+        self.request.start_time = start_time.into();
+        self
+    }
+
+    /// End of the time range, exclusive.
+    pub fn end_time(mut self, end_time: impl Into<String>) -> Self {
+        self.request.end_time = end_time.into();
+        self
+    }
+
+    /// Return processed values instead of raw samples: one value of the
+    /// named aggregate (e.g. `TimeAverage2`, `Minimum`, `Maximum`) per
+    /// interval, given as a time span such as `1:00:00`.
+    pub fn aggregate(mut self, name: impl Into<String>, interval: impl Into<String>) -> Self {
+        self.request.aggregate_name = Some(name.into());
+        self.request.aggregate_interval = Some(interval.into());
+        self
+    }
+
+    /// Also return the bounding samples: the last one at or before the
+    /// start time and the first one at or after the end time, so values
+    /// can be interpolated or integrated across the whole range.
+    pub fn include_bounds(mut self, include: bool) -> Self {
+        self.request.include_bounds = include;
+        self
+    }
+
+    /// Most samples per tag the server returns in one response; the
+    /// client asks for the rest with the continuation the server sends.
+    /// Without it the server's own limit applies.
+    pub fn max_size(mut self, max_size: u32) -> Self {
+        self.request.max_size = Some(max_size);
+        self
+    }
+
+    pub fn build(self) -> TagDataRequest {
+        self.request
+    }
+}
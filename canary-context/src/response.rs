@@ -0,0 +1,340 @@
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufReader, Read};
+
+const SNIPPET_LEN: usize = 200;
+
+/// Context of one tag as returned by getTagContext. Fields are read through
+/// accessors so new response fields can be added without a breaking change.
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct TagContext {
+    tag_name: String,
+    tag_context: TagDetails,
+}
+
+impl TagContext {
+    pub fn tag_name(&self) -> &str {
+        &self.tag_name
+    }
+
+    pub fn details(&self) -> &TagDetails {
+        &self.tag_context
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct TagDetails {
+    historian_item_id: Option<String>,
+    source_item_id: Option<String>,
+    oldest_time_stamp: String,
+    latest_time_stamp: String,
+}
+
+impl TagDetails {
+    pub fn historian_item_id(&self) -> Option<&str> {
+        self.historian_item_id.as_deref()
+    }
+
+    pub fn source_item_id(&self) -> Option<&str> {
+        self.source_item_id.as_deref()
+    }
+
+    pub fn oldest_time_stamp(&self) -> &str {
+        &self.oldest_time_stamp
+    }
+
+    pub fn latest_time_stamp(&self) -> &str {
+        &self.latest_time_stamp
+    }
+}
+
+/// Samples of one tag as returned by getTagData, oldest first.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TagData {
+    tag_name: String,
+    values: Vec<TagValue>,
+}
+
+impl TagData {
+    pub fn tag_name(&self) -> &str {
+        &self.tag_name
+    }
+
+    pub fn values(&self) -> &[TagValue] {
+        &self.values
+    }
+}
+
+/// One timestamp/value/quality sample. The value is whatever JSON type the
+/// historian stored (number, string, bool or null); the quality is the raw
+/// OPC quality code when the server sends one.
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[non_exhaustive]
+pub struct TagValue {
+    #[serde(rename(deserialize = "t"))]
+    timestamp: String,
+    #[serde(rename(deserialize = "v"))]
+    value: serde_json::Value,
+    #[serde(rename(deserialize = "q"), default)]
+    quality: Option<u32>,
+}
+
+impl TagValue {
+    pub fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    pub fn value(&self) -> &serde_json::Value {
+        &self.value
+    }
+
+    pub fn quality(&self) -> Option<u32> {
+        self.quality
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BrowseTagsResponse {
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TagContextResponse {
+    data: Vec<TagContext>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TagDataResponse {
+    data: Option<BTreeMap<String, Vec<TagValue>>>,
+    #[serde(default)]
+    continuation: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct QualitiesResponse {
+    qualities: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserTokenResponse {
+    user_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiveDataTokenResponse {
+    live_data_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LiveDataResponse {
+    data: Option<BTreeMap<String, Vec<TagValue>>>,
+    #[serde(default)]
+    continuation: serde_json::Value,
+}
+
+/// One poll of a live data session: the samples that arrived since the
+/// previous poll and the opaque marker to send with the next one.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct LiveData {
+    data: Vec<TagData>,
+    continuation: serde_json::Value,
+}
+
+impl LiveData {
+    pub fn data(&self) -> &[TagData] {
+        &self.data
+    }
+
+    pub fn continuation(&self) -> &serde_json::Value {
+        &self.continuation
+    }
+
+    pub fn into_data(self) -> Vec<TagData> {
+        self.data
+    }
+}
+
+/// One response of a getTagData read: samples up to the server's size limit
+/// and, when there are more, the marker to send to continue the read.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TagDataPage {
+    data: Vec<TagData>,
+    continuation: serde_json::Value,
+}
+
+impl TagDataPage {
+    pub fn data(&self) -> &[TagData] {
+        &self.data
+    }
+
+    /// Null when the page ends the read.
+    pub fn continuation(&self) -> &serde_json::Value {
+        &self.continuation
+    }
+
+    pub fn into_data(self) -> Vec<TagData> {
+        self.data
+    }
+
+    /// Appends another page's samples to the matching tags, keeping tags in
+    /// name order.
+    pub fn merge(&mut self, page: TagDataPage) {
+        let mut tags: BTreeMap<String, Vec<TagValue>> = self.data.drain(..).map(|tag| (tag.tag_name, tag.values)).collect();
+        for tag in page.data {
+            tags.entry(tag.tag_name).or_default().extend(tag.values);
+        }
+        self.data = tag_data(Some(tags));
+        self.continuation = page.continuation;
+    }
+}
+
+/// A response that cannot be turned into data: a non-success status, a body
+/// over the configured size limit, or a body that is not the JSON the
+/// endpoint documents (typically an HTML error page from a proxy).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ResponseError {
+    Status { endpoint: &'static str, status: u16, body: String },
+    TooLarge { endpoint: &'static str, limit: u64 },
+    Malformed { endpoint: &'static str, source: serde_json::Error, body: String },
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseError::Status { endpoint, status, body } => write!(f, "{} returned HTTP {}: {}", endpoint, status, body),
+            ResponseError::TooLarge { endpoint, limit } => write!(f, "{} response exceeded the maximum response size of {} bytes", endpoint, limit),
+            ResponseError::Malformed { endpoint, source, body } => write!(f, "{} returned an unreadable response ({}): {}", endpoint, source, body),
+        }
+    }
+}
+
+impl Error for ResponseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ResponseError::Status { .. } | ResponseError::TooLarge { .. } => None,
+            ResponseError::Malformed { source, .. } => Some(source),
+        }
+    }
+}
+
+pub fn check_status(endpoint: &'static str, status: u16, body: &[u8]) -> Result<(), ResponseError> {
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(ResponseError::Status { endpoint, status, body: snippet(body) })
+    }
+}
+
+/// Parses a browseTags body as it is read, without buffering it. Reading
+/// more than `max_size` bytes fails with `ResponseError::TooLarge`.
+pub fn parse_browse_tags<R: Read>(body: R, max_size: Option<u64>) -> Result<Vec<String>, ResponseError> {
+    parse::<BrowseTagsResponse, R>("browseTags", body, max_size).map(|response| response.tags.unwrap_or_default())
+}
+
+/// Parses a getTagContext body as it is read; see `parse_browse_tags`.
+pub fn parse_tag_context<R: Read>(body: R, max_size: Option<u64>) -> Result<Vec<TagContext>, ResponseError> {
+    parse::<TagContextResponse, R>("getTagContext", body, max_size).map(|response| response.data)
+}
+
+/// Parses a getTagData body as it is read; see `parse_browse_tags`. Tags
+/// are returned in name order.
+pub fn parse_tag_data<R: Read>(body: R, max_size: Option<u64>) -> Result<Vec<TagData>, ResponseError> {
+    parse_tag_data_page(body, max_size).map(|page| page.data)
+}
+
+/// Parses a getTagData body with its continuation; see `parse_browse_tags`.
+pub fn parse_tag_data_page<R: Read>(body: R, max_size: Option<u64>) -> Result<TagDataPage, ResponseError> {
+    parse::<TagDataResponse, R>("getTagData", body, max_size).map(|response| TagDataPage { data: tag_data(response.data), continuation: response.continuation })
+}
+
+/// Parses a getQualities body; see `parse_browse_tags`. Entries whose key
+/// is not a quality code are skipped.
+pub fn parse_qualities<R: Read>(body: R, max_size: Option<u64>) -> Result<BTreeMap<u32, String>, ResponseError> {
+    parse::<QualitiesResponse, R>("getQualities", body, max_size).map(|response| response.qualities.unwrap_or_default().into_iter().filter_map(|(code, name)| Some((code.parse().ok()?, name))).collect())
+}
+
+/// Parses a getUserToken body; see `parse_browse_tags`.
+pub fn parse_user_token<R: Read>(body: R, max_size: Option<u64>) -> Result<String, ResponseError> {
+    parse::<UserTokenResponse, R>("getUserToken", body, max_size).map(|response| response.user_token)
+}
+
+/// Parses a getLiveDataToken body; see `parse_browse_tags`.
+pub fn parse_live_data_token<R: Read>(body: R, max_size: Option<u64>) -> Result<String, ResponseError> {
+    parse::<LiveDataTokenResponse, R>("getLiveDataToken", body, max_size).map(|response| response.live_data_token)
+}
+
+/// Parses a getLiveData body; see `parse_browse_tags`.
+pub fn parse_live_data<R: Read>(body: R, max_size: Option<u64>) -> Result<LiveData, ResponseError> {
+    parse::<LiveDataResponse, R>("getLiveData", body, max_size).map(|response| LiveData { data: tag_data(response.data), continuation: response.continuation })
+}
+
+fn tag_data(data: Option<BTreeMap<String, Vec<TagValue>>>) -> Vec<TagData> {
+    data.unwrap_or_default().into_iter().map(|(tag_name, values)| TagData { tag_name, values }).collect()
+}
+
+fn parse<T: DeserializeOwned, R: Read>(endpoint: &'static str, body: R, max_size: Option<u64>) -> Result<T, ResponseError> {
+    let mut body = BodyReader { inner: body, read: 0, limit: max_size, prefix: Vec::new() };
+    match serde_json::from_reader(BufReader::new(&mut body)) {
+        Ok(value) => Ok(value),
+        Err(_) if body.exceeded() => Err(ResponseError::TooLarge { endpoint, limit: max_size.unwrap_or_default() }),
+        Err(source) => Err(ResponseError::Malformed { endpoint, source, body: snippet(&body.prefix) }),
+    }
+}
+
+/// Counts bytes as they are read, failing once past `limit`, and keeps the
+/// first few hundred bytes so malformed bodies can be quoted in errors.
+struct BodyReader<R> {
+    inner: R,
+    read: u64,
+    limit: Option<u64>,
+    prefix: Vec<u8>,
+}
+
+impl<R> BodyReader<R> {
+    fn exceeded(&self) -> bool {
+        self.limit.is_some_and(|limit| self.read > limit)
+    }
+}
+
+impl<R: Read> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if self.exceeded() {
+            return Err(io::Error::other("response size limit exceeded"));
+        }
+        let keep = (SNIPPET_LEN * 4).saturating_sub(self.prefix.len()).min(n);
+        self.prefix.extend_from_slice(&buf[..keep]);
+        Ok(n)
+    }
+}
+
+/// The start of a body for an error message, with any credentials the
+/// server echoed back from the request blanked out.
+fn snippet(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+    let text = crate::audit::redact_text(text.trim());
+    let text = text.as_ref();
+    match text.char_indices().nth(SNIPPET_LEN) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Wrapper for credentials that must never be printed. `Debug` and
+/// `Display` both render as `[REDACTED]`; the value is only reachable
+/// through `expose`, which should be called where it is sent to the server.
+#[derive(Clone)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
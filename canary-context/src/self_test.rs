@@ -0,0 +1,197 @@
+use crate::encoding::Encoding;
+use crate::exit;
+use crate::examples;
+use crate::output::{self, ByteCount, Record, WriteOptions};
+use crate::sqlite;
+use canary_context::request::{BrowseRequest, TagDataRequest};
+use canary_context::CanaryClient;
+use clap::Command;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+const TOKEN: &str = "self-test-token";
+const TAGS: [&str; 5] = ["Site.Line1.Temperature", "Site.Line1.Pressure", "Site.Line2.Flow", "Site.Line2.Motor.Speed", "Site.Line3.Level"];
+
+type Calls = Arc<Mutex<Vec<String>>>;
+
+/// Runs the client and every writer against a mock Canary server on the
+/// loopback interface and prints one PASS or FAIL line per check. Nothing
+/// leaves the machine; files go to a temporary directory that is removed
+/// afterwards.
+pub async fn run(cli: impl Fn() -> Command) -> Result<(), Box<dyn Error>> {
+    let mut failures = 0;
+    let mut checks = 0;
+    let mut report = |name: &str, result: Result<String, Box<dyn Error>>| {
+        checks += 1;
+        match result {
+            Ok(detail) => println!("PASS  {} ({})", name, detail),
+            Err(e) => {
+                failures += 1;
+                println!("FAIL  {}: {}", name, e);
+            }
+        }
+    };
+
+    report("argument parsing", examples::check(&cli).map(|count| format!("{} examples", count)));
+    let calls = Calls::default();
+    let url = serve(calls.clone()).await?;
+    let client = CanaryClient::builder(TOKEN).server(url.as_str()).context_batch_size(2).retries(0).connect().await?;
+
+    let tags = client.browse_tags(&BrowseRequest::builder().deep(true).build()).await;
+    report("browse", tags.as_ref().map_err(|e| exit::chain_text(e.as_ref()).into()).and_then(|tags| expect(tags.len() == TAGS.len(), format!("{} tags", tags.len()))));
+    let tags = tags.unwrap_or_default();
+
+    let contexts = client.get_tag_context(&tags).await;
+    let batches = calls.lock().unwrap().iter().filter(|endpoint| *endpoint == "getTagContext").count();
+    report("context batching", contexts.as_ref().map_err(|e| exit::chain_text(e.as_ref()).into()).and_then(|contexts| expect(contexts.len() == tags.len() && batches == tags.len().div_ceil(2), format!("{} contexts in {} batches of 2", contexts.len(), batches))));
+    let contexts = contexts.unwrap_or_default();
+
+    match tags.first() {
+        Some(tag) => {
+            let data = client.get_tag_data(&TagDataRequest::builder().tag(tag).start_time("Now-1Hour").max_size(1).build()).await;
+            let pages = calls.lock().unwrap().iter().filter(|endpoint| *endpoint == "getTagData").count();
+            report("data paging", data.map_err(|e| exit::chain_text(e.as_ref()).into()).and_then(|data| {
+                let samples: usize = data.iter().map(|tag| tag.values().len()).sum();
+                expect(samples == 2 && pages == 2, format!("{} samples in {} pages", samples, pages))
+            }));
+        }
+        None => report("data paging", Err("no tag to read; browse returned none".into())),
+    }
+
+    let dir = TempDir::create()?;
+    let records: Vec<Record> = contexts.iter().map(|context| Record::new(context, "2024-01-01T00:00:00Z", None)).collect();
+    let options = WriteOptions { escape_formulas: true, bom: false, compact_json: false, encoding: Encoding::Utf8, max_bytes: None, compression: None, uncompressed: ByteCount::default(), metadata: HashMap::new(), date_format: None };
+    for format in output::FORMATS {
+        report(&format!("{} writer", format), write(format, &records, &dir.0, options.clone()));
+    }
+    drop(dir);
+
+    if failures > 0 {
+        return Err(format!("self-test failed: {} of {} checks failed", failures, checks).into());
+    }
+    println!("Self-test passed: {} checks.", checks);
+    Ok(())
+}
+
+/// The directory the writers write to, removed when dropped so a failed
+/// check or an error return does not leave it behind.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn create() -> Result<Self, Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("canary-context-self-test-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir)?;
+        Ok(TempDir(dir))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn expect(ok: bool, detail: String) -> Result<String, Box<dyn Error>> {
+    if ok {
+        Ok(detail)
+    } else {
+        Err(format!("unexpected result: {}", detail).into())
+    }
+}
+
+fn write(format: &str, records: &[Record], dir: &Path, options: WriteOptions) -> Result<String, Box<dyn Error>> {
+    let path = dir.join(format!("tags.{}", format));
+    let filename = path.to_str().ok_or("temporary path is not valid UTF-8")?;
+    match format {
+        "csv" => output::save_to_csv(records, filename, options)?,
+        "txt" => output::save_to_txt(records, filename, options, None)?,
+        "json" => output::save_to_json(records, filename, options)?,
+        "ndjson" => output::save_to_ndjson(records, filename, options)?,
+        "xlsx" => output::save_to_xlsx(records, filename, options)?,
+        "arrow" => output::save_to_arrow(records, filename, options)?,
+        _ => sqlite::save_to_sqlite(records, filename, None, false)?,
+    }
+    let bytes = std::fs::metadata(&path)?.len();
+    // Text formats are read back; for the binary ones the size has to do.
+    let rows = match format {
+        "csv" => csv::Reader::from_path(&path)?.records().count(),
+        "json" => serde_json::from_str::<Vec<Value>>(&std::fs::read_to_string(&path)?)?.len(),
+        "ndjson" => std::fs::read_to_string(&path)?.lines().map(serde_json::from_str::<Value>).collect::<Result<Vec<_>, _>>()?.len(),
+        "txt" => std::fs::read_to_string(&path)?.matches("TagName: ").count(),
+        _ => return expect(bytes > 0, format!("{} bytes", bytes)),
+    };
+    expect(bytes > 0 && rows == records.len(), format!("{} rows, {} bytes", rows, bytes))
+}
+
+/// Starts the mock server and returns its URL.
+async fn serve(calls: Calls) -> Result<String, Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle(stream, calls.clone()));
+        }
+    });
+    Ok(url)
+}
+
+/// Answers HTTP/1.1 requests on one connection until the client closes it.
+async fn handle(stream: TcpStream, calls: Calls) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if stream.read_line(&mut request_line).await? == 0 {
+            return Ok(());
+        }
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse()?;
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await?;
+        let endpoint = request_line.split_whitespace().nth(1).unwrap_or_default().rsplit('/').next().unwrap_or_default().to_string();
+        let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+        calls.lock().unwrap().push(endpoint.clone());
+        let response = respond(&endpoint, &body).to_string();
+        let head = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n", response.len());
+        stream.get_mut().write_all(head.as_bytes()).await?;
+        stream.get_mut().write_all(response.as_bytes()).await?;
+    }
+}
+
+fn respond(endpoint: &str, body: &Value) -> Value {
+    let requested: Vec<&str> = body["tags"].as_array().map(|tags| tags.iter().filter_map(Value::as_str).collect()).unwrap_or_default();
+    match endpoint {
+        "browseTags" => json!({ "statusCode": "Good", "errors": [], "tags": TAGS }),
+        "getTagContext" => {
+            let data: Vec<Value> = requested
+                .iter()
+                .map(|tag| json!({ "tagName": tag, "tagContext": { "historianItemId": format!("self-test-{}", tag), "sourceItemId": null, "oldestTimeStamp": "2024-01-01T00:00:00.0000000+00:00", "latestTimeStamp": "2024-06-01T00:00:00.0000000+00:00" } }))
+                .collect();
+            json!({ "statusCode": "Good", "errors": [], "data": data })
+        }
+        "getTagData" => {
+            // One sample per page, two pages per tag.
+            let page = body["continuation"].as_u64().unwrap_or_default();
+            let data: serde_json::Map<String, Value> = requested.iter().map(|tag| (tag.to_string(), json!([{ "t": format!("2024-01-01T00:0{}:00.0000000+00:00", page), "v": page, "q": 192 }]))).collect();
+            json!({ "statusCode": "Good", "errors": [], "data": data, "continuation": if page == 0 { json!(1) } else { Value::Null } })
+        }
+        _ => json!({ "statusCode": "Good", "errors": [] }),
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::{BTreeMap, HashSet};
+
+/// Two tags in the same folder whose names are suspiciously close.
+pub struct NearDuplicate<'a> {
+    pub folder: &'a str,
+    pub first: &'a str,
+    pub second: &'a str,
+    pub distance: usize,
+}
+
+/// Pairs of tag names in the same folder (the path before the last `.`)
+/// whose last segments are at most `max_distance` edits apart. Names that
+/// differ only in digits, such as Pump1 and Pump2, are numbered siblings
+/// rather than typos and are left out, as are exact duplicates.
+pub fn near_duplicates(tags: &[String], max_distance: usize) -> Vec<NearDuplicate<'_>> {
+    let mut folders: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for tag in tags.iter().filter(|tag| seen.insert(tag.as_str())) {
+        let (folder, name) = tag.rsplit_once('.').unwrap_or(("", tag));
+        folders.entry(folder).or_default().push(name);
+    }
+
+    let mut pairs = Vec::new();
+    for (folder, mut names) in folders {
+        names.sort_unstable();
+        for (i, first) in names.iter().enumerate() {
+            for second in &names[i + 1..] {
+                if first.chars().count().abs_diff(second.chars().count()) > max_distance || differ_only_in_digits(first, second) {
+                    continue;
+                }
+                let distance = levenshtein(first, second);
+                if distance <= max_distance {
+                    pairs.push(NearDuplicate { folder, first, second, distance });
+                }
+            }
+        }
+    }
+    pairs
+}
+
+fn differ_only_in_digits(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.chars().zip(b.chars()).all(|(x, y)| x == y || (x.is_ascii_digit() && y.is_ascii_digit()))
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(x != *y);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
@@ -0,0 +1,154 @@
+use crate::output::{DataRecord, Record};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, Connection, Transaction};
+use serde_json::Value;
+use std::error::Error;
+
+/// Schema changes in release order. A database's `user_version` is the
+/// number of them applied to it. Raw samples have an empty aggregate so it
+/// can be part of the key.
+const MIGRATIONS: [&str; 2] = ["CREATE TABLE IF NOT EXISTS tag_context (
+    tag_name TEXT PRIMARY KEY NOT NULL,
+    historian_item_id TEXT,
+    source_item_id TEXT,
+    oldest_time_stamp TEXT NOT NULL,
+    latest_time_stamp TEXT NOT NULL,
+    retrieved_at TEXT NOT NULL,
+    historian TEXT,
+    row_id TEXT
+);
+CREATE TABLE IF NOT EXISTS tag_data (
+    tag_name TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    aggregate TEXT NOT NULL DEFAULT '',
+    value,
+    quality INTEGER,
+    quality_name TEXT,
+    PRIMARY KEY (tag_name, timestamp, aggregate)
+);",
+    "ALTER TABLE tag_context ADD COLUMN alias TEXT;
+ALTER TABLE tag_context ADD COLUMN dataset TEXT;
+ALTER TABLE tag_context ADD COLUMN unit TEXT;",
+];
+
+/// Creates or updates the `tag_context` table of the database at
+/// `filename`, one row per tag name. Tags from earlier runs that this run
+/// did not return are kept, so repeated exports refresh the file in place.
+/// All of a run's rows go in one transaction: a run that fails or is killed
+/// part way leaves the table as the previous run wrote it.
+pub fn save_to_sqlite(data: &[Record], filename: &str, max_bytes: Option<u64>, auto_migrate: bool) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::open(filename)?;
+    let transaction = connection.transaction()?;
+    migrate(&transaction, filename, auto_migrate)?;
+    {
+        let mut upsert = transaction.prepare(
+            "INSERT INTO tag_context (tag_name, historian_item_id, source_item_id, oldest_time_stamp, latest_time_stamp, retrieved_at, historian, row_id, alias, dataset, unit)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+             ON CONFLICT (tag_name) DO UPDATE SET
+                historian_item_id = excluded.historian_item_id,
+                source_item_id = excluded.source_item_id,
+                oldest_time_stamp = excluded.oldest_time_stamp,
+                latest_time_stamp = excluded.latest_time_stamp,
+                retrieved_at = excluded.retrieved_at,
+                historian = excluded.historian,
+                row_id = excluded.row_id,
+                alias = excluded.alias,
+                dataset = excluded.dataset,
+                unit = excluded.unit",
+        )?;
+        for record in data {
+            let details = record.tag_context;
+            upsert.execute(params![
+                record.tag_name,
+                details.historian_item_id(),
+                details.source_item_id(),
+                details.oldest_time_stamp(),
+                details.latest_time_stamp(),
+                record.retrieved_at,
+                record.historian,
+                record.row_id,
+                record.alias,
+                record.dataset,
+                record.unit,
+            ])?;
+        }
+    }
+    commit(transaction, max_bytes)
+}
+
+/// Creates or updates the `tag_data` table of the database at `filename`,
+/// one row per tag, timestamp and aggregate, in one transaction like
+/// `save_to_sqlite`. Values keep their JSON type.
+pub fn save_data_to_sqlite(data: &[DataRecord], filename: &str, max_bytes: Option<u64>, auto_migrate: bool) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::open(filename)?;
+    let transaction = connection.transaction()?;
+    migrate(&transaction, filename, auto_migrate)?;
+    {
+        let mut upsert = transaction.prepare(
+            "INSERT INTO tag_data (tag_name, timestamp, aggregate, value, quality, quality_name)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (tag_name, timestamp, aggregate) DO UPDATE SET
+                value = excluded.value,
+                quality = excluded.quality,
+                quality_name = excluded.quality_name",
+        )?;
+        for record in data {
+            upsert.execute(params![
+                record.tag_name,
+                record.sample.timestamp(),
+                record.aggregate.unwrap_or_default(),
+                sql_value(record.sample.value()),
+                record.sample.quality(),
+                record.quality_name,
+            ])?;
+        }
+    }
+    commit(transaction, max_bytes)
+}
+
+/// Brings the database's tables up to this release's schema. A new
+/// database gets every migration. One an older release wrote, including
+/// one from before the schema was versioned (version 0 with tables), is
+/// only changed with `auto_migrate`; the first migration only creates
+/// missing tables, so it suits both.
+fn migrate(transaction: &Transaction, filename: &str, auto_migrate: bool) -> Result<(), Box<dyn Error>> {
+    let version: usize = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(format!("{} has schema version {}, newer than the version {} this release writes; use a newer canary-context", filename, version, MIGRATIONS.len()).into());
+    }
+    let written = version > 0 || transaction.query_row("SELECT count(*) > 0 FROM sqlite_master WHERE type = 'table'", [], |row| row.get(0))?;
+    if written && version < MIGRATIONS.len() && !auto_migrate {
+        return Err(format!("{} has schema version {}, older than the version {} this release writes; rerun with --auto_migrate to upgrade it", filename, version, MIGRATIONS.len()).into());
+    }
+    for migration in &MIGRATIONS[version..] {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    Ok(())
+}
+
+fn sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(value) => SqlValue::Integer(i64::from(*value)),
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => SqlValue::Integer(value),
+            None => number.as_f64().map_or(SqlValue::Null, SqlValue::Real),
+        },
+        Value::String(value) => SqlValue::Text(value.clone()),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+/// Rolls back instead when the database would grow past `max_bytes`, so
+/// the file keeps its previous contents.
+fn commit(transaction: Transaction, max_bytes: Option<u64>) -> Result<(), Box<dyn Error>> {
+    if let Some(limit) = max_bytes {
+        let size: u64 = transaction.query_row("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()", [], |row| row.get(0))?;
+        if size > limit {
+            return Err(format!("output file would exceed the maximum output size of {} bytes", limit).into());
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
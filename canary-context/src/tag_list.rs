@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+
+/// Columns a CSV tags file may have after `tag_name`.
+const COLUMNS: [&str; 3] = ["alias", "dataset", "unit"];
+
+/// Per-tag columns of a CSV tags file, carried into the export's rows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagParams {
+    pub alias: Option<String>,
+    pub dataset: Option<String>,
+    pub unit: Option<String>,
+}
+
+/// Tag names in file order and the per-tag columns by name.
+pub type TagList = (Vec<String>, HashMap<String, TagParams>);
+
+/// Tag names of a `--tags_file`, with the per-tag columns of a CSV one. A
+/// file whose first line starts with a `tag_name` header is CSV with any of
+/// the alias, dataset and unit columns; otherwise it has one name per line.
+/// Blank lines and `#` comments are skipped in both. A tag listed twice
+/// keeps the columns of its last row.
+pub fn read(source: &str) -> Result<TagList, Box<dyn Error>> {
+    let text = match source {
+        "-" => io::read_to_string(io::stdin())?,
+        path => std::fs::read_to_string(path).map_err(|e| format!("cannot read tags file {}: {}", path, e))?,
+    };
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).peekable();
+    if lines.peek().is_none_or(|line| line.split(',').next().map(|field| field.trim().trim_matches('"')) != Some("tag_name")) {
+        return Ok((lines.map(String::from).collect(), HashMap::new()));
+    }
+
+    let invalid = |e: csv::Error| format!("cannot read tags file {}: {}", source, e);
+    let csv = lines.collect::<Vec<_>>().join("\n");
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(csv.as_bytes());
+    let headers = reader.headers().map_err(invalid)?.clone();
+    if let Some(unknown) = headers.iter().skip(1).find(|header| !COLUMNS.contains(header)) {
+        return Err(format!("unknown column {:?} in tags file {}; the columns after tag_name can be {}", unknown, source, COLUMNS.join(", ")).into());
+    }
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let (alias, dataset, unit) = (column("alias"), column("dataset"), column("unit"));
+
+    let mut tags = Vec::new();
+    let mut params = HashMap::new();
+    for row in reader.records() {
+        let row = row.map_err(invalid)?;
+        let cell = |index: Option<usize>| index.and_then(|index| row.get(index)).filter(|value| !value.is_empty()).map(String::from);
+        let Some(name) = cell(Some(0)) else {
+            continue;
+        };
+        params.insert(name.clone(), TagParams { alias: cell(alias), dataset: cell(dataset), unit: cell(unit) });
+        tags.push(name);
+    }
+    Ok((tags, params))
+}
@@ -0,0 +1,144 @@
+use crate::output::{escape_line_breaks, Record};
+use regex::Regex;
+use std::error::Error;
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    TagName,
+    HistorianItemId,
+    SourceItemId,
+    OldestTimeStamp,
+    LatestTimeStamp,
+    RetrievedAt,
+    Historian,
+    RowId,
+    Alias,
+    Dataset,
+    Unit,
+}
+
+/// Fields a TXT template can reference as `{name}`.
+const FIELDS: [(&str, Field); 11] = [
+    ("tag_name", Field::TagName),
+    ("historian_item_id", Field::HistorianItemId),
+    ("source_item_id", Field::SourceItemId),
+    ("oldest_time_stamp", Field::OldestTimeStamp),
+    ("latest_time_stamp", Field::LatestTimeStamp),
+    ("retrieved_at", Field::RetrievedAt),
+    ("historian", Field::Historian),
+    ("row_id", Field::RowId),
+    ("alias", Field::Alias),
+    ("dataset", Field::Dataset),
+    ("unit", Field::Unit),
+];
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+/// A per-record TXT layout. `{field}` is replaced with the record's value
+/// (empty when absent); `{{` and `}}` produce literal braces.
+#[derive(Debug)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err("unclosed '{' in template; use '{{' for a literal brace".into());
+                    }
+                    let field = FIELDS.iter().find(|(field, _)| *field == name.trim()).map(|(_, field)| *field).ok_or_else(|| {
+                        let names: Vec<&str> = FIELDS.iter().map(|(name, _)| *name).collect();
+                        format!("unknown template field '{{{}}}'; expected one of {}", name, names.join(", "))
+                    })?;
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    segments.push(Segment::Field(field));
+                }
+                '}' => return Err("unmatched '}' in template; use '}}' for a literal brace".into()),
+                c => literal.push(c),
+            }
+        }
+        segments.push(Segment::Literal(literal));
+        Ok(Template { segments })
+    }
+
+    pub fn render(&self, record: &Record) -> String {
+        let details = record.tag_context;
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Field(field) => out.push_str(&match field {
+                    Field::TagName => escape_line_breaks(&record.tag_name),
+                    Field::HistorianItemId => details.historian_item_id().unwrap_or("").to_string(),
+                    Field::SourceItemId => details.source_item_id().unwrap_or("").to_string(),
+                    Field::OldestTimeStamp => details.oldest_time_stamp().to_string(),
+                    Field::LatestTimeStamp => details.latest_time_stamp().to_string(),
+                    Field::RetrievedAt => record.retrieved_at.to_string(),
+                    Field::Historian => record.historian.unwrap_or("").to_string(),
+                    Field::RowId => record.row_id.clone().unwrap_or_default(),
+                    Field::Alias => escape_line_breaks(record.alias.unwrap_or("")),
+                    Field::Dataset => escape_line_breaks(record.dataset.unwrap_or("")),
+                    Field::Unit => escape_line_breaks(record.unit.unwrap_or("")),
+                }),
+            }
+        }
+        out
+    }
+
+    /// Blanks the `{retrieved_at}` values of text rendered with this
+    /// template. Each record is found by its literal text; other fields
+    /// match anything within a line, and the run timestamp only its
+    /// `2024-01-01T00:00:00Z` shape.
+    pub fn blank_retrieved_at(&self, text: &str) -> String {
+        if !self.segments.iter().any(|segment| matches!(segment, Segment::Field(Field::RetrievedAt))) {
+            return text.to_string();
+        }
+        let pattern: String = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => regex::escape(literal),
+                Segment::Field(Field::RetrievedAt) => r"(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z)".to_string(),
+                Segment::Field(_) => r"[^\n]*?".to_string(),
+            })
+            .collect();
+        let record = Regex::new(&pattern).expect("escaped template is a valid pattern");
+        let mut out = String::with_capacity(text.len());
+        let mut end = 0;
+        for captures in record.captures_iter(text) {
+            for value in captures.iter().skip(1).flatten() {
+                out.push_str(&text[end..value.start()]);
+                end = value.end();
+            }
+        }
+        out.push_str(&text[end..]);
+        out
+    }
+}
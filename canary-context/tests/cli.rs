@@ -0,0 +1,1490 @@
+#![cfg(feature = "integration-tests")]
+
+mod common;
+
+use common::{export, MockCanary, MockClickHouse, MockConfig, MockNats, MockQuestDb, MockRedis};
+use serde_json::Value;
+use std::collections::HashSet;
+
+const TAGS: [&str; 3] = ["Plant1.Line1.Temperature", "Plant1.Line1.Pressure", "Plant1.Line2.Flow"];
+
+#[test]
+fn exports_csv() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "csv", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let mut reader = csv::Reader::from_path(path).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    assert_eq!(headers.iter().collect::<Vec<_>>(), ["tag_name", "historian_item_id", "source_item_id", "oldest_time_stamp", "latest_time_stamp", "retrieved_at"]);
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), TAGS.len());
+    assert_eq!(&rows[0][0], TAGS[0]);
+    assert_eq!(&rows[1][2], "");
+}
+
+#[test]
+fn exports_txt() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "txt", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let text = std::fs::read_to_string(path).unwrap();
+    assert_eq!(text.matches("TagName: ").count(), TAGS.len());
+    assert!(text.contains("  HistorianItemId: hist-2\n"));
+}
+
+#[test]
+fn txt_template_sets_record_layout() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let template = dir.path().join("layout.txt");
+    std::fs::write(&template, "{tag_name} -> {{{historian_item_id}}}\n").unwrap();
+    let (output, path) = export(&canary, dir.path(), "txt", &["--txt_template", template.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let text = std::fs::read_to_string(path).unwrap();
+    assert_eq!(text.lines().count(), TAGS.len());
+    assert!(text.lines().all(|line| line.contains(" -> {hist-") && line.ends_with('}')));
+    assert!(text.lines().any(|line| line.starts_with(TAGS[1])));
+
+    std::fs::write(&template, "{description}\n").unwrap();
+    let (output, _) = export(&canary, dir.path(), "txt", &["--txt_template", template.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown template field '{description}'"));
+
+    std::fs::write(&template, "{tag_name} -> {historian_item_id").unwrap();
+    let (output, _) = export(&canary, dir.path(), "txt", &["--txt_template", template.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unclosed '{' in template"));
+}
+
+#[test]
+fn exports_json() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "json", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let mut json: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    let retrieved_at: Vec<Value> = json.as_array_mut().unwrap().iter_mut().map(|row| row.as_object_mut().unwrap().remove("retrievedAt").unwrap()).collect();
+    assert!(retrieved_at.iter().all(|value| value == &retrieved_at[0] && value.as_str().unwrap().ends_with('Z')));
+    let mut sorted = TAGS;
+    sorted.sort();
+    let expected: Vec<Value> = sorted.iter().map(|tag| common::tag_context(&canary_tags(), tag)).collect();
+    assert_eq!(json, Value::Array(expected));
+}
+
+#[test]
+fn compact_json_is_one_line_and_stable() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let runs: Vec<String> = (0..2)
+        .map(|_| {
+            let (output, path) = export(&canary, dir.path(), "json", &["--json_compact"]);
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            let text = std::fs::read_to_string(path).unwrap();
+            let retrieved_at = text.find("\"retrievedAt\":\"").unwrap();
+            text.replace(&text[retrieved_at..retrieved_at + 37], "")
+        })
+        .collect();
+    assert_eq!(runs[0].lines().count(), 1);
+    assert_eq!(runs[0], runs[1]);
+}
+
+#[test]
+fn exports_ndjson_one_object_per_line() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "ndjson", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<Value> = std::fs::read_to_string(path).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.iter().map(|line| line["tagName"].as_str().unwrap()).collect::<Vec<_>>(), TAGS);
+    assert_eq!(lines[1]["tagContext"], common::tag_context(&canary_tags(), TAGS[1])["tagContext"]);
+
+    let path = dir.path().join("tags.ndjson");
+    let output = common::run_cli(&canary, &["tags", "--output_format", "ndjson", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), TAGS.iter().map(|tag| format!("\"{}\"\n", tag)).collect::<String>());
+
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "ndjson", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<Value> = std::fs::read_to_string(&path).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines[0], serde_json::json!({ "tagName": TAGS[0], "timestamp": "2024-01-01T00:00:00.0000000-08:00", "value": 1.5, "quality": 192, "qualityName": "Good" }));
+    assert_eq!(lines.len(), 2);
+}
+
+#[test]
+fn writes_the_output_to_stdout_for_a_dash() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let output = common::run_cli(&canary, &["export", "--output_format", "ndjson", "--output_file", "-"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<Value> = String::from_utf8(output.stdout).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.iter().map(|line| line["tagName"].as_str().unwrap()).collect::<Vec<_>>(), TAGS);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Data saved to stdout in ndjson format.") && stderr.contains("3 records written.") && stderr.contains("Run ID: "), "{}", stderr);
+
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "csv", "--output_file", "-"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(csv::Reader::from_reader(output.stdout.as_slice()).records().count(), 2);
+
+    let output = common::run_cli(&canary, &["export", "--output_format", "sqlite", "--output_file", "-"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sqlite output needs a database file"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn browses_then_fetches_context_for_every_tag() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, _) = export(&canary, dir.path(), "json", &[]);
+    assert!(output.status.success());
+
+    let requests = canary.requests();
+    assert_eq!(requests[0].0, "browseTags");
+    assert_eq!(requests[0].1["deep"], true);
+    let requested: Vec<&str> = requests[1..].iter().flat_map(|(_, body)| body["tags"].as_array().unwrap().iter().map(|tag| tag.as_str().unwrap())).collect();
+    assert_eq!(requested, TAGS);
+}
+
+#[test]
+fn reports_when_no_tags_found() {
+    let canary = MockCanary::with_tags(&[]);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "csv", &[]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error: no tags found"));
+    assert!(!path.exists());
+}
+
+#[test]
+fn browse_reports_when_no_tags_found() {
+    let canary = MockCanary::with_tags(&[]);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tags.csv");
+    for args in [&["browse"][..], &["near_duplicates"], &["tags", "--output_format", "csv", "--output_file", path.to_str().unwrap()]] {
+        let output = common::run_cli(&canary, args);
+        assert_eq!(output.status.code(), Some(4), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Error: no tags found"));
+    }
+    assert!(!path.exists());
+}
+
+#[test]
+fn fails_on_server_error() {
+    let canary = MockCanary::start(MockConfig { tags: vec!["A".to_string()], fail_with: Some(503), ..Default::default() });
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "csv", &["--retry_delay", "10"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("503"));
+    assert_eq!(canary.correlation_ids().len(), 4);
+    assert!(String::from_utf8_lossy(&output.stderr).contains(canary.correlation_ids().last().unwrap()));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Hint: the Canary Views service is unavailable"));
+    assert_eq!(output.status.code(), Some(6));
+    assert!(!path.exists());
+}
+
+#[test]
+fn retries_transient_failures_with_backoff() {
+    let canary = MockCanary::start(MockConfig { tags: canary_tags(), transient_failures: vec![502, 503], ..Default::default() });
+    let output = common::run_cli(&canary, &["browse", "--retry_delay", "10"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().collect::<Vec<_>>(), TAGS);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warning: browseTags failed (correlation ID ") && stderr.contains("): browseTags returned HTTP 502") && stderr.contains("(attempt 1 of 3)"), "{}", stderr);
+    assert!(stderr.contains("(attempt 2 of 3)"), "{}", stderr);
+    assert_eq!(canary.requests().len(), 3);
+
+    let canary = MockCanary::start(MockConfig { tags: canary_tags(), transient_failures: vec![500], ..Default::default() });
+    let output = common::run_cli(&canary, &["browse", "--retry_delay", "10"]);
+    assert!(!output.status.success());
+    assert_eq!(canary.requests().len(), 1);
+
+    let canary = MockCanary::start(MockConfig { tags: canary_tags(), transient_failures: vec![503, 503], ..Default::default() });
+    let output = common::run_cli(&canary, &["browse", "--retries", "1", "--retry_delay", "10"]);
+    assert!(!output.status.success());
+    assert_eq!(canary.requests().len(), 2);
+}
+
+#[test]
+fn logs_at_the_requested_verbosity_and_format() {
+    let stderr = |args: &[&str]| {
+        let canary = MockCanary::start(MockConfig { tags: canary_tags(), transient_failures: vec![503], ..Default::default() });
+        let output = common::run_cli(&canary, &[&["browse", "--retry_delay", "10"], args].concat());
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let default = stderr(&[]);
+    assert!(default.starts_with("Warning: browseTags failed"), "{}", default);
+    assert!(!default.contains("Debug:"));
+    assert_eq!(stderr(&["-q"]), "");
+
+    let verbose = stderr(&["-v"]);
+    let call = verbose.lines().find(|line| line.starts_with("Debug: API call") && line.contains("status=200")).unwrap();
+    assert!(call.contains("endpoint=\"browseTags\"") && call.contains("/api/v2/browseTags") && call.contains("elapsed_ms=") && call.contains("bytes="), "{}", call);
+    assert!(!verbose.contains(common::TOKEN));
+    let trace = stderr(&["-vv"]);
+    assert!(trace.contains("Trace: API request") && trace.contains("[REDACTED]") && !trace.contains(common::TOKEN), "{}", trace);
+
+    let json: Vec<Value> = stderr(&["--log_format", "json"]).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(json.len(), 1);
+    assert_eq!(json[0]["level"], "WARN");
+    assert!(json[0]["fields"]["message"].as_str().unwrap().starts_with("browseTags failed"));
+}
+
+#[test]
+fn hints_at_the_likely_cause_of_common_failures() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let hint = |args: &[&str], code: i32| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).args(args).arg("browse").output().unwrap();
+        assert_eq!(output.status.code(), Some(code));
+        String::from_utf8_lossy(&output.stderr).lines().find_map(|line| line.strip_prefix("Hint: ").map(String::from)).unwrap_or_default()
+    };
+
+    assert!(hint(&["--canary", &canary.url, "--api_token", "wrong"], 2).contains("check --api_token"));
+    assert!(hint(&["--canary", &canary.url, "--api_token", common::TOKEN, "--api_version", "api/v1"], 6).contains("no browseTags endpoint under /api/v1; check --api_version"));
+    assert!(hint(&["--canary", "http://127.0.0.1:1", "--api_token", common::TOKEN, "--retries", "0"], 3).contains("nothing is listening at that address"));
+
+    // The Canary flags do not help with a sink that is down.
+    let dir = tempfile::tempdir().unwrap();
+    let (output, _) = export(&canary, dir.path(), "csv", &["--clickhouse_url", "http://127.0.0.1:1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Hint: "), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).args(["browse", "--no_such_flag"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(64));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).arg("--help").output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Exit codes:"));
+}
+
+fn canary_tags() -> Vec<String> {
+    TAGS.iter().map(|tag| tag.to_string()).collect()
+}
+
+#[test]
+fn escapes_formula_cells_in_csv() {
+    let canary = MockCanary::with_tags(&["=HYPERLINK(\"http://example.com\")", "@SUM(A1)", "Plant1.Temp"]);
+    let dir = tempfile::tempdir().unwrap();
+
+    let (output, path) = export(&canary, dir.path(), "csv", &[]);
+    assert!(output.status.success());
+    let names: Vec<String> = csv::Reader::from_path(&path).unwrap().records().map(|row| row.unwrap()[0].to_string()).collect();
+    assert_eq!(names, ["'=HYPERLINK(\"http://example.com\")", "'@SUM(A1)", "Plant1.Temp"]);
+
+    let (output, path) = export(&canary, dir.path(), "csv", &["--no_formula_escape"]);
+    assert!(output.status.success());
+    let names: Vec<String> = csv::Reader::from_path(&path).unwrap().records().map(|row| row.unwrap()[0].to_string()).collect();
+    assert_eq!(names, ["=HYPERLINK(\"http://example.com\")", "@SUM(A1)", "Plant1.Temp"]);
+}
+
+#[test]
+fn labels_rows_when_browsing_several_historians() {
+    let canary = MockCanary::with_tags(&["North.Line1.Temp", "South.Line1.Temp", "West.Line1.Temp"]);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "csv", &["--historian", "North", "--historian", "South"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let mut reader = csv::Reader::from_path(path).unwrap();
+    assert_eq!(reader.headers().unwrap().get(6), Some("historian"));
+    let rows: Vec<(String, String)> = reader.records().map(|row| row.unwrap()).map(|row| (row[0].to_string(), row[6].to_string())).collect();
+    assert_eq!(rows, [("North.Line1.Temp".to_string(), "North".to_string()), ("South.Line1.Temp".to_string(), "South".to_string())]);
+}
+
+#[test]
+fn scopes_the_browse_with_path_depth_and_search() {
+    let canary = MockCanary::with_tags(&["North.Line1.Temp", "North.Line1.Motor.Speed", "North.Line2.Temp", "South.Line1.Temp"]);
+    let browse = |args: &[&str]| {
+        let output = common::run_cli(&canary, &[&["browse"], args].concat());
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().lines().map(String::from).collect::<Vec<_>>()
+    };
+
+    assert_eq!(browse(&["--path", "North.Line1"]), ["North.Line1.Temp", "North.Line1.Motor.Speed"]);
+    assert_eq!(browse(&["--path", "North.Line1", "--no_deep"]), ["North.Line1.Temp"]);
+    assert_eq!(browse(&["--search", "temp"]), ["North.Line1.Temp", "North.Line2.Temp", "South.Line1.Temp"]);
+    assert_eq!(browse(&["--historian", "North", "--historian", "South", "--path", "Line1", "--search", "temp"]), ["North.Line1.Temp", "South.Line1.Temp"]);
+    let (_, body) = canary.requests().pop().unwrap();
+    assert_eq!((body["path"].as_str(), body["deep"].as_bool(), body["search"].as_str()), (Some("South.Line1"), Some(true), Some("temp")));
+}
+
+#[test]
+fn filters_tag_names_before_fetching_context() {
+    let canary = MockCanary::with_tags(&["Plant1.Line1.Temperature", "Plant1.Line1.Motor.Temperature", "Plant1.Line2.Pressure", "Plant2.Line1.Temperature"]);
+    let dir = tempfile::tempdir().unwrap();
+    let output_file = dir.path().join("tags.txt");
+    let output = common::run_cli(&canary, &["export", "--output_format", "txt", "--output_file", output_file.to_str().unwrap(), "--filter", "Plant1.*Temperature", "--glob", "*/*/Temp*"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let (_, context) = canary.requests().into_iter().find(|(endpoint, _)| endpoint == "getTagContext").unwrap();
+    assert_eq!(context["tags"], Value::from(vec!["Plant1.Line1.Temperature"]));
+
+    let output = common::run_cli(&canary, &["browse", "--glob", "Plant1/**/Temperature"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().collect::<Vec<_>>(), ["Plant1.Line1.Temperature", "Plant1.Line1.Motor.Temperature"]);
+
+    let requests = canary.requests().len();
+    let output = common::run_cli(&canary, &["browse", "--filter", "Plant1.(Temp"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--filter"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(canary.requests().len(), requests);
+}
+
+#[test]
+fn keeps_tag_names_with_spaces_slashes_and_unicode_intact() {
+    let tags = ["Plant 1.Line/A.Temp \u{B0}C", "Plant 1.Line/A.Flow, m\u{B3}/h", "Plant 1.Line B.Temp \u{B0}C", "Plant 2.Line/A.Temp \u{B0}C"];
+    let canary = MockCanary::with_tags(&tags);
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = common::run_cli(&canary, &["browse", "--path", "Plant 1.Line/A", "--search", "\u{B0}c"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}\n", tags[0]));
+    let (_, browse) = canary.requests().into_iter().find(|(endpoint, _)| endpoint == "browseTags").unwrap();
+    assert_eq!(browse["path"], "Plant 1.Line/A");
+
+    let browse = |args: &[&str]| String::from_utf8(common::run_cli(&canary, &[&["browse"], args].concat()).stdout).unwrap();
+    assert_eq!(browse(&["--glob", "*/*/Temp*"]).lines().collect::<Vec<_>>(), [tags[0], tags[2], tags[3]]);
+    assert_eq!(browse(&["--glob", "Plant 1/Line\\/A/*"]).lines().collect::<Vec<_>>(), [tags[0], tags[1]]);
+    assert_eq!(browse(&["--glob", "**/*\\/h"]).lines().collect::<Vec<_>>(), [tags[1]]);
+    assert_eq!(browse(&["--filter", "^Plant 1\\.Line/A\\."]).lines().collect::<Vec<_>>(), [tags[0], tags[1]]);
+
+    let (output, path) = export(&canary, dir.path(), "csv", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let names: Vec<String> = csv::Reader::from_path(path).unwrap().records().map(|row| row.unwrap()[0].to_string()).collect();
+    assert_eq!(names, tags);
+    let mut sorted = tags;
+    sorted.sort();
+    let (_, path) = export(&canary, dir.path(), "json", &[]);
+    let json: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(json.as_array().unwrap().iter().map(|tag| tag["tagName"].as_str().unwrap()).collect::<Vec<_>>(), sorted);
+    let (_, path) = export(&canary, dir.path(), "txt", &[]);
+    let text = std::fs::read_to_string(path).unwrap();
+    assert!(tags.iter().all(|tag| text.contains(&format!("TagName: {}\n", tag))), "{}", text);
+    let (_, path) = export(&canary, dir.path(), "xlsx", &[]);
+    let strings = std::io::read_to_string(zip::ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap().by_name("xl/sharedStrings.xml").unwrap()).unwrap();
+    assert!(tags.iter().all(|tag| strings.contains(&format!("<t>{}</t>", tag))), "{}", strings);
+}
+
+#[test]
+fn reads_the_tag_list_from_a_file_or_stdin_instead_of_browsing() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let tags_file = dir.path().join("tags.txt");
+    std::fs::write(&tags_file, format!("# from the MES\n{}\n\n  {}  \n", TAGS[2], TAGS[0])).unwrap();
+    let (output, path) = export(&canary, dir.path(), "json", &["--tags_file", tags_file.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(json.as_array().unwrap().iter().map(|tag| tag["tagName"].as_str().unwrap()).collect::<Vec<_>>(), [TAGS[0], TAGS[2]]);
+    assert_eq!(canary.requests().iter().map(|(endpoint, _)| endpoint.as_str()).collect::<Vec<_>>(), ["getTagContext"]);
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+        .args(["--canary", &canary.url, "--api_token", common::TOKEN, "browse", "--tags_file", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), format!("{}\n", TAGS[1]).as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}\n", TAGS[1]));
+    assert!(!canary.requests().iter().any(|(endpoint, _)| endpoint == "browseTags"));
+
+    let output = common::run_cli(&canary, &["browse", "--tags_file", "-", "--historian", "North"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn writes_per_tag_columns_of_a_csv_tags_file() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let tags_file = dir.path().join("tags.csv");
+    std::fs::write(&tags_file, format!("tag_name,alias,unit\n# from the MES\n{},Line 1 temperature,degC\n\n{},,bar\n", TAGS[0], TAGS[2])).unwrap();
+    let tags = ["--tags_file", tags_file.to_str().unwrap()];
+
+    let (output, path) = export(&canary, dir.path(), "csv", &tags);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(&path).unwrap();
+    assert_eq!(reader.headers().unwrap().iter().skip(6).collect::<Vec<_>>(), ["alias", "unit"]);
+    let rows: Vec<Vec<String>> = reader.records().map(|row| row.unwrap().iter().skip(6).map(String::from).collect()).collect();
+    assert_eq!(rows, [vec!["Line 1 temperature".to_string(), "degC".to_string()], vec![String::new(), "bar".to_string()]]);
+
+    let (output, path) = export(&canary, dir.path(), "sqlite", &tags);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let alias: Option<String> = connection.query_row("SELECT alias FROM tag_context WHERE tag_name = ?1", [TAGS[0]], |row| row.get(0)).unwrap();
+    assert_eq!(alias.as_deref(), Some("Line 1 temperature"));
+
+    std::fs::write(&tags_file, format!("tag_name,units\n{},degC\n", TAGS[0])).unwrap();
+    let (output, _) = export(&canary, dir.path(), "csv", &tags);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown column \"units\""), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn enforces_per_run_limits() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+
+    let (output, path) = export(&canary, dir.path(), "csv", &["--max_tags", "2"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("found 3 tags, more than --max_tags 2"));
+    assert!(!path.exists());
+    let (output, path) = export(&canary, dir.path(), "csv", &["--max_tags", "2", "--truncate_tags"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning: found 3 tags; keeping the first 2"));
+    assert_eq!(csv::Reader::from_path(path).unwrap().records().count(), 2);
+
+    let requests = canary.requests().len();
+    let (output, _) = export(&canary, dir.path(), "csv", &["--max_requests", "2", "--batch_size", "1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the limit of 2 API calls"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(canary.requests().len() - requests, 2);
+
+    let (output, _) = export(&canary, dir.path(), "json", &["--max_output_bytes", "100"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("maximum output size of 100 bytes"));
+    let (output, _) = export(&canary, dir.path(), "json", &["--max_output_bytes", "1MB"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn fails_over_to_the_next_reachable_server() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("export.csv");
+    let servers = format!("http://127.0.0.1:1,{}", canary.url);
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+        .args(["--canary", &servers, "--api_token", common::TOKEN, "export", "--output_format", "csv", "--output_file", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("http://127.0.0.1:1 is unreachable"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!("Served by {}.", canary.url)));
+}
+
+#[test]
+fn baseline_check_reports_deviations() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let baseline = dir.path().join("baseline.json");
+    let baseline = baseline.to_str().unwrap();
+
+    let output = common::run_cli(&canary, &["baseline", "write", baseline]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = common::run_cli(&canary, &["baseline", "check", baseline]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Baseline check passed: 3 tags"));
+
+    canary.set_tags(&[TAGS[0].to_string(), TAGS[2].to_string(), "Plant1.Line3.Level".to_string()]);
+    let output = common::run_cli(&canary, &["baseline", "check", baseline]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("missing:    Plant1.Line1.Pressure"));
+    assert!(stdout.contains("unexpected: Plant1.Line3.Level"));
+    assert!(stdout.contains("changed:    Plant1.Line2.Flow historianItemId expected 'hist-2', found 'hist-1'"));
+    assert!(!stdout.contains('\x1b'));
+
+    let colored = |args: &[&str], env: &[(&str, &str)]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+            .args(["--canary", &canary.url, "--api_token", common::TOKEN, "baseline", "check", baseline])
+            .args(args)
+            .envs(env.iter().copied())
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let stdout = colored(&[], &[("CLICOLOR_FORCE", "1")]);
+    assert!(stdout.contains("\x1b[31mmissing:    Plant1.Line1.Pressure\x1b[0m"));
+    assert!(stdout.contains("\x1b[32munexpected: Plant1.Line3.Level\x1b[0m"));
+    assert!(stdout.contains("\x1b[33mchanged:    Plant1.Line2.Flow"));
+    assert!(!colored(&["--no_color"], &[("CLICOLOR_FORCE", "1")]).contains('\x1b'));
+    assert!(!colored(&[], &[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")]).contains('\x1b'));
+
+    let output = common::run_cli(&canary, &["baseline", "check", baseline, "--diff_format", "json"]);
+    assert_eq!(output.status.code(), Some(1));
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["passed"], false);
+    assert_eq!(report["deviations"][0], serde_json::json!({ "kind": "missing", "tagName": "Plant1.Line1.Pressure" }));
+    assert_eq!(report["deviations"][1], serde_json::json!({ "kind": "changed", "tagName": "Plant1.Line2.Flow", "field": "historianItemId", "expected": "hist-2", "actual": "hist-1" }));
+}
+
+#[test]
+fn baseline_check_matches_renamed_tags_by_key() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let baseline = dir.path().join("baseline.json");
+    let baseline = baseline.to_str().unwrap();
+    let output = common::run_cli(&canary, &["baseline", "write", baseline]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    canary.set_tags(&["Area1.Line1.Temperature".to_string(), TAGS[1].to_string(), TAGS[2].to_string()]);
+    let output = common::run_cli(&canary, &["baseline", "check", baseline]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("missing:    Plant1.Line1.Temperature") && stdout.contains("unexpected: Area1.Line1.Temperature"));
+
+    let output = common::run_cli(&canary, &["baseline", "check", baseline, "--key", "historian_item_id"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("changed:    Plant1.Line1.Temperature tagName expected 'Plant1.Line1.Temperature', found 'Area1.Line1.Temperature'"));
+    assert!(stdout.contains("Baseline check failed: 1 deviations"));
+
+    let output = common::run_cli(&canary, &["baseline", "check", baseline, "--key", "historian_item_id", "--compare", "source_item_id"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn lists_the_tags_of_a_failed_context_batch_and_goes_on() {
+    let canary = MockCanary::start(MockConfig { tags: TAGS.iter().map(|tag| tag.to_string()).collect(), failing_context: vec![TAGS[1].to_string()], ..Default::default() });
+    let dir = tempfile::tempdir().unwrap();
+    let errors_file = dir.path().join("errors.csv");
+    let output = common::run_cli(&canary, &["--batch_size", "1", "export", "--output_format", "ndjson", "--output_file", "-", "--errors_file", errors_file.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let exported: Vec<Value> = String::from_utf8_lossy(&output.stdout).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(exported.iter().map(|line| line["tagName"].as_str().unwrap()).collect::<Vec<_>>(), [TAGS[0], TAGS[2]]);
+    let errors: Vec<csv::StringRecord> = csv::Reader::from_path(&errors_file).unwrap().records().map(Result::unwrap).collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(&errors[0][0], TAGS[1]);
+    assert!(errors[0][1].contains("getTagContext returned HTTP 500"), "{:?}", errors);
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("1 tags returned no context; see {}", errors_file.display())));
+
+    let output = common::run_cli(&canary, &["--batch_size", "1", "export", "--output_format", "csv", "--output_file", dir.path().join("tags.csv").to_str().unwrap(), "--strict"]);
+    assert!(!output.status.success());
+    assert!(dir.path().join("context_errors.csv").exists());
+}
+
+#[test]
+fn reports_tags_without_context() {
+    let canary = MockCanary::start(MockConfig { tags: TAGS.iter().map(|tag| tag.to_string()).collect(), without_context: vec![TAGS[1].to_string()], ..Default::default() });
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "csv", &[]);
+    assert!(output.status.success());
+    assert_eq!(csv::Reader::from_path(&path).unwrap().records().count(), 2);
+    let errors = std::fs::read_to_string(dir.path().join("context_errors.csv")).unwrap();
+    assert_eq!(errors, format!("tag_name,error\n{},no context returned\n", TAGS[1]));
+
+    std::fs::remove_file(&path).unwrap();
+    let (output, path) = export(&canary, dir.path(), "csv", &["--strict"]);
+    assert!(!output.status.success());
+    assert!(!path.exists());
+
+    // The errors file is written like the export: encoding, BOM and formula escaping.
+    let formula = "=Plant1.Line1.Cmd";
+    let canary = MockCanary::start(MockConfig { tags: vec![TAGS[0].to_string(), formula.to_string()], without_context: vec![formula.to_string()], ..Default::default() });
+    let (output, _) = export(&canary, dir.path(), "csv", &["--encoding", "utf-16le", "--bom"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let bytes = std::fs::read(dir.path().join("context_errors.csv")).unwrap();
+    let expected: Vec<u8> = format!("\u{FEFF}tag_name,error\n'{},no context returned\n", formula).encode_utf16().flat_map(u16::to_le_bytes).collect();
+    assert_eq!(bytes, expected);
+    let (output, _) = export(&canary, dir.path(), "csv", &["--no_formula_escape"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(dir.path().join("context_errors.csv")).unwrap(), format!("tag_name,error\n{},no context returned\n", formula));
+}
+
+#[test]
+fn writes_bom_and_nfc_tag_names() {
+    let decomposed = "Usine.De\u{301}bit";
+    let canary = MockCanary::with_tags(&[decomposed]);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "csv", &["--bom"]);
+    assert!(output.status.success());
+
+    let bytes = std::fs::read(path).unwrap();
+    assert!(bytes.starts_with(b"\xEF\xBB\xBFtag_name,"));
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(text.contains("Usine.D\u{e9}bit,"));
+    assert!(!text.contains(decomposed));
+}
+
+#[test]
+fn row_ids_are_stable_across_runs() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let ids = |kind: &str| -> Vec<String> {
+        let (output, path) = export(&canary, dir.path(), "json", &["--row_id", kind]);
+        assert!(output.status.success());
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        json.as_array().unwrap().iter().map(|row| row["rowId"].as_str().unwrap().to_string()).collect()
+    };
+
+    let hashes = ids("hash");
+    assert_eq!(hashes, ids("hash"));
+    assert!(hashes.iter().all(|id| id.len() == 64));
+    let uuids = ids("uuid");
+    assert_eq!(uuids, ids("uuid"));
+    assert!(uuids.iter().all(|id| id.len() == 36 && &id[14..15] == "5"));
+}
+
+#[test]
+fn git_commit_only_commits_inventory_changes() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let repo = tempfile::tempdir().unwrap();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git").arg("-C").arg(repo.path()).args(args).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    git(&["init", "--quiet"]);
+    git(&["config", "user.name", "Test"]);
+    git(&["config", "user.email", "test@example.com"]);
+    let export = |format: &str, extra: &[&str]| {
+        let file = format!("inventory.{}", format);
+        let args = [&["export", "--output_format", format, "--output_file", &file, "--git_commit", repo.path().to_str().unwrap()][..], extra].concat();
+        let output = common::run_cli(&canary, &args);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let run = || export("csv", &[]);
+
+    assert!(run().contains("Update tag inventory: 3 tags from"));
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert!(run().contains("No inventory changes"));
+    assert_eq!(git(&["status", "--porcelain"]), "");
+
+    canary.set_tags(&["Plant1.Line1.Temperature".to_string(), "Plant1.Line3.Level".to_string()]);
+    assert!(run().contains("Update tag inventory: 2 tags from"));
+    assert_eq!(git(&["rev-list", "--count", "HEAD"]).trim(), "2");
+    assert!(git(&["log", "-1", "--format=%b"]).contains("lines added"));
+
+    // A server timestamp in the run timestamp's format is still a change.
+    canary.set_latest_time_stamp("2024-06-01T12:00:00Z");
+    assert!(run().contains("Update tag inventory"));
+    canary.set_latest_time_stamp("2024-06-01T12:00:01Z");
+    assert!(run().contains("Update tag inventory"));
+    assert_eq!(git(&["rev-list", "--count", "HEAD"]).trim(), "4");
+
+    let template = repo.path().join("record.tmpl");
+    std::fs::write(&template, "{tag_name} {latest_time_stamp} {retrieved_at}\n").unwrap();
+    for (format, extra) in [("json", &[][..]), ("ndjson", &[]), ("txt", &[]), ("txt", &["--txt_template", template.to_str().unwrap()])] {
+        canary.set_latest_time_stamp("2024-06-01T12:00:00Z");
+        assert!(export(format, extra).contains("Update tag inventory"), "{} {:?}", format, extra);
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert!(export(format, extra).contains("No inventory changes"), "{} {:?}", format, extra);
+        canary.set_latest_time_stamp("2024-06-01T12:00:01Z");
+        assert!(export(format, extra).contains("Update tag inventory"), "{} {:?}", format, extra);
+    }
+}
+
+#[test]
+fn inserts_rows_into_clickhouse_in_batches() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let clickhouse = MockClickHouse::start();
+    let dir = tempfile::tempdir().unwrap();
+    let (output, _) = export(&canary, dir.path(), "csv", &["--clickhouse_url", &clickhouse.url, "--clickhouse_table", "plant.tags", "--clickhouse_batch_size", "2"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Inserted 3 rows into ClickHouse table plant.tags in 2 batches."));
+
+    let inserts = clickhouse.inserts();
+    assert_eq!(inserts.iter().map(|(_, body)| body.lines().count()).collect::<Vec<_>>(), [2, 1]);
+    let (params, body) = &inserts[0];
+    assert_eq!(params["query"], "INSERT INTO plant.tags FORMAT JSONEachRow");
+    assert_eq!(params["async_insert"], "1");
+    let row: Value = serde_json::from_str(body.lines().next().unwrap()).unwrap();
+    assert_eq!(row["tag_name"], TAGS[0]);
+    assert_eq!(row["historian_item_id"], "hist-0");
+    assert_eq!(clickhouse.authorizations(), [None, None]);
+}
+
+#[test]
+fn sends_clickhouse_credentials_in_the_url_as_basic_auth() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let clickhouse = MockClickHouse::start();
+    let url = clickhouse.url.replacen("http://", "http://loader:s%40cret@", 1);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.csv");
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "csv", "--output_file", path.to_str().unwrap(), "--clickhouse_url", &url]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // base64 of loader:s@cret
+    assert_eq!(clickhouse.authorizations(), [Some("Basic bG9hZGVyOnNAY3JldA==".to_string())]);
+}
+
+#[test]
+fn inserts_samples_into_clickhouse() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let clickhouse = MockClickHouse::start();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.csv");
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "csv", "--output_file", path.to_str().unwrap(), "--clickhouse_url", &clickhouse.url]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Inserted 2 samples into ClickHouse table canary_tag_data in 1 batches."));
+
+    let inserts = clickhouse.inserts();
+    let (params, body) = &inserts[0];
+    assert_eq!(params["query"], "INSERT INTO canary_tag_data FORMAT JSONEachRow");
+    let row: Value = serde_json::from_str(body.lines().next().unwrap()).unwrap();
+    assert_eq!(row, serde_json::json!({ "tag_name": TAGS[0], "timestamp": "2024-01-01T00:00:00.0000000-08:00", "value": 1.5, "quality": 192, "quality_name": "Good" }));
+}
+
+#[test]
+fn sends_samples_to_questdb_as_line_protocol() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let questdb = MockQuestDb::start();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.csv");
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "csv", "--output_file", path.to_str().unwrap(), "--questdb_addr", &questdb.addr, "--questdb_table", "plant.samples"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Sent 2 samples to QuestDB table plant.samples."));
+
+    let lines = questdb.lines();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], format!("plant.samples,tag_name={} value=1.5,quality=192i,quality_name=\"Good\" 1704096000000000000", TAGS[0]));
+}
+
+#[test]
+fn estimates_a_data_read_from_a_first_page() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.csv");
+    let run = |extra: &[&str]| {
+        let args = [&["data", TAGS[0], TAGS[1], "--start_time", "2024-01-01T00:00:00-08:00", "--end_time", "2024-01-01T01:00:00-08:00", "--estimate", "--max_size", "2", "--output_format", "csv", "--output_file", path.to_str().unwrap()][..], extra].concat();
+        common::run_cli(&canary, &args)
+    };
+
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("add --yes to read without asking"));
+    assert!(!path.exists());
+
+    let output = run(&["--yes"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // One sample a minute over an hour, for each of the two tags.
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Estimate for 2 tags: about 120 samples, "), "{}", String::from_utf8_lossy(&output.stdout));
+    assert_eq!(csv::Reader::from_path(&path).unwrap().records().count(), 4);
+    // A probe for each run, then the full read of the confirmed one.
+    let reads = canary.requests().into_iter().filter(|(endpoint, body)| endpoint == "getTagData" && body["maxSize"] == 2).count();
+    assert_eq!(reads, 3);
+
+    // The server caps pages at 3 samples, below the default probe size, so
+    // the probe is truncated though no tag reached it: 3 samples over 90s.
+    let capped = MockCanary::start(MockConfig { tags: canary_tags(), max_page_size: Some(3), ..Default::default() });
+    let args = ["data", TAGS[0], TAGS[1], "--start_time", "2024-01-01T00:00:00-08:00", "--end_time", "2024-01-01T01:00:00-08:00", "--bounds", "outside", "--estimate", "--yes", "--output_format", "csv", "--output_file", path.to_str().unwrap()];
+    let output = common::run_cli(&capped, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Estimate for 2 tags: about 160 samples, "), "{}", String::from_utf8_lossy(&output.stdout));
+    assert_eq!(csv::Reader::from_path(&path).unwrap().records().count(), 8);
+}
+
+#[test]
+fn compares_sample_counts_and_hashes_between_servers() {
+    let first = MockCanary::with_tags(&TAGS);
+    let same = MockCanary::with_tags(&TAGS);
+    let output = common::run_cli(&first, &["compare_data", TAGS[0], TAGS[1], "--other_canary", &same.url, "--start_time", "Now-1Hour", "--window", "60"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Comparison passed: 4 windows match."));
+    assert_eq!(same.requests().iter().filter(|(endpoint, _)| endpoint == "getTagData").count(), 1);
+
+    let migrated = MockCanary::start(MockConfig { tags: canary_tags(), missing_samples: vec![TAGS[0].to_string()], ..Default::default() });
+    let output = common::run_cli(&first, &["compare_data", TAGS[0], TAGS[1], "--other_canary", &migrated.url, "--start_time", "Now-1Hour", "--window", "60"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("{} 2024-01-01T08:01:00Z: 1 samples on the first server, 0 on the second", TAGS[0])), "{}", stdout);
+    assert!(stdout.contains("Comparison failed: 1 of 4 windows differ."), "{}", stdout);
+
+    let output = common::run_cli(&first, &["compare_data", TAGS[0], TAGS[1], "--other_canary", &migrated.url, "--start_time", "Now-1Hour"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!("{} 2024-01-01T08:00:00Z: 2 samples on the first server, 1 on the second", TAGS[0])));
+}
+
+#[test]
+fn caches_context_in_redis_hashes_with_ttl() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let redis = MockRedis::start();
+    let dir = tempfile::tempdir().unwrap();
+    let (output, _) = export(&canary, dir.path(), "csv", &["--redis_url", &redis.url, "--redis_ttl", "60"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let commands = redis.commands();
+    let key = format!("canary:tag:{}", TAGS[1]);
+    let hset = commands.iter().find(|command| command[0] == "HSET" && command[1] == key).unwrap();
+    assert!(hset.windows(2).any(|pair| pair == ["historian_item_id", "hist-1"]));
+    let hdel = commands.iter().find(|command| command[0] == "HDEL" && command[1] == key).unwrap();
+    assert!(hdel.contains(&"source_item_id".to_string()));
+    assert!(commands.iter().any(|command| command == &["EXPIRE", key.as_str(), "60"]));
+    assert_eq!(commands.iter().filter(|command| command[0] == "EXPIRE").count(), TAGS.len());
+}
+
+#[test]
+fn publishes_rows_to_jetstream_with_dedup_ids() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let nats = MockNats::start();
+    let dir = tempfile::tempdir().unwrap();
+    let (output, _) = export(&canary, dir.path(), "csv", &["--nats_url", &nats.url, "--nats_subject", "plant.inventory"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let messages = nats.messages();
+    assert_eq!(messages.len(), TAGS.len());
+    assert!(messages.iter().all(|message| message.subject == "plant.inventory"));
+    let ids: HashSet<&str> = messages.iter().map(|message| message.headers.lines().find_map(|line| line.strip_prefix("Nats-Msg-Id: ")).unwrap()).collect();
+    assert_eq!(ids.len(), TAGS.len());
+    let row: Value = serde_json::from_str(&messages[0].payload).unwrap();
+    assert_eq!(row["tagName"], TAGS[0]);
+}
+
+#[test]
+fn browse_and_context_subcommands_print_to_stdout() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+        .args(["browse", "--canary", &canary.url, "--api_token", common::TOKEN])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().collect::<Vec<_>>(), TAGS);
+
+    let output = common::run_cli(&canary, &["context", TAGS[2]]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json, Value::Array(vec![common::tag_context(&canary_tags(), TAGS[2])]));
+    assert_eq!(canary.requests().iter().map(|(endpoint, _)| endpoint.as_str()).collect::<Vec<_>>(), ["browseTags", "getTagContext"]);
+}
+
+#[test]
+fn reports_near_duplicate_tag_names_within_a_folder() {
+    let canary = MockCanary::with_tags(&[
+        "Plant1.Line1.Temperature",
+        "Plant1.Line1.Temprature",
+        "Plant1.Line1.Pump1",
+        "Plant1.Line1.Pump2",
+        "Plant1.Line2.Temperatur",
+        "Plant1.Line1.Pressure",
+    ]);
+    let output = common::run_cli(&canary, &["near_duplicates"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Plant1.Line1.Temperature ~ Plant1.Line1.Temprature (distance 1)\nSummary: 1 near-duplicate pairs among 6 tags.\n");
+
+    let output = common::run_cli(&canary, &["near_duplicates", "--max_distance", "0"]);
+    assert!(String::from_utf8(output.stdout).unwrap().starts_with("Summary: 0 near-duplicate pairs"));
+}
+
+#[test]
+fn tags_subcommand_writes_names_without_context() {
+    let canary = MockCanary::with_tags(&["Plant1.Line1.Temperature", "=Plant1.Line2.Flow"]);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tags.txt");
+    let output = common::run_cli(&canary, &["tags", "--output_format", "txt", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Plant1.Line1.Temperature\n=Plant1.Line2.Flow\n");
+    assert_eq!(canary.requests().iter().map(|(endpoint, _)| endpoint.as_str()).collect::<Vec<_>>(), ["browseTags"]);
+
+    let path = dir.path().join("tags.json");
+    let output = common::run_cli(&canary, &["tags", "--output_format", "json", "--json_compact", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), r#"["Plant1.Line1.Temperature","=Plant1.Line2.Flow"]"#);
+
+    let path = dir.path().join("tags.csv");
+    let output = common::run_cli(&canary, &["tags", "--output_format", "csv", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "tag_name\nPlant1.Line1.Temperature\n'=Plant1.Line2.Flow\n");
+}
+
+#[test]
+fn looks_up_tags_by_historian_item_id() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let output = common::run_cli(&canary, &["lookup", "hist-2", "hist-9"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json, Value::Array(vec![common::tag_context(&canary_tags(), TAGS[2])]));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning: no tag has historian item ID hist-9."));
+
+    let output = common::run_cli(&canary, &["lookup", "hist-9"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("none of the 1 historian item IDs belong to a tag"));
+}
+
+#[test]
+fn version_subcommand_needs_no_server() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).arg("version").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("canary-context {}\n", env!("CARGO_PKG_VERSION")));
+}
+
+#[test]
+fn data_subcommand_writes_samples() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.csv");
+    let output = common::run_cli(&canary, &["data", TAGS[0], TAGS[2], "--start_time", "Now-1Hour", "--output_format", "csv", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let (endpoint, body) = canary.requests().pop().unwrap();
+    assert_eq!(endpoint, "getTagData");
+    assert_eq!((body["startTime"].as_str(), body["endTime"].as_str()), (Some("Now-1Hour"), Some("Now")));
+    let mut reader = csv::Reader::from_path(&path).unwrap();
+    assert_eq!(reader.headers().unwrap(), vec!["tag_name", "timestamp", "value", "quality", "quality_name"]);
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(&rows[0], vec![TAGS[0], "2024-01-01T00:00:00.0000000-08:00", "1.5", "192", "Good"]);
+    assert_eq!((&rows[1][3], &rows[1][4]), ("24", "Bad: Comm Failure"));
+
+    let path = dir.path().join("data.json");
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "json", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(json[1], serde_json::json!({ "tagName": TAGS[0], "timestamp": "2024-01-01T00:01:00.0000000-08:00", "value": 2.5, "quality": 24, "qualityName": "Bad: Comm Failure" }));
+}
+
+#[test]
+fn data_subcommand_reads_bounding_samples_outside_the_range() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.csv");
+    let run = |bounds: &str| common::run_cli(&canary, &["data", TAGS[0], "--start_time", "2024-01-01T00:00:00-08:00", "--end_time", "2024-01-01T00:02:00-08:00", "--bounds", bounds, "--output_format", "csv", "--output_file", path.to_str().unwrap()]);
+    let timestamps = || csv::Reader::from_path(&path).unwrap().records().map(|row| row.unwrap()[1].to_string()).collect::<Vec<_>>();
+
+    assert!(run("outside").status.success());
+    assert_eq!(canary.requests().pop().unwrap().1["includeBounds"], true);
+    assert_eq!(timestamps(), ["2023-12-31T23:59:30.0000000-08:00", "2024-01-01T00:00:00.0000000-08:00", "2024-01-01T00:01:00.0000000-08:00", "2024-01-01T00:02:30.0000000-08:00"]);
+
+    assert!(run("inside").status.success());
+    assert!(canary.requests().pop().unwrap().1.get("includeBounds").is_none());
+    assert_eq!(timestamps().len(), 2);
+}
+
+#[test]
+fn data_subcommand_refreshes_quality_names_from_the_server() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.txt");
+    let output = common::run_cli(&canary, &["data", TAGS[0], TAGS[2], "--start_time", "Now-1Hour", "--refresh_qualities", "--output_format", "txt", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let (_, body) = canary.requests().into_iter().find(|(endpoint, _)| endpoint == "getQualities").unwrap();
+    assert_eq!(body["qualities"], serde_json::json!([24, 192]));
+    let text = std::fs::read_to_string(path).unwrap();
+    assert!(text.contains("  Quality: 192\n  QualityName: Server quality 192\n"), "{}", text);
+    assert!(text.contains("  Quality: 24\n  QualityName: Server quality 24\n"), "{}", text);
+}
+
+#[test]
+fn live_subcommand_streams_ndjson_and_revokes_the_session() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let output = common::run_cli(&canary, &["live", TAGS[0], TAGS[2], "--poll_interval", "10", "--polls", "3"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let lines: Vec<Value> = String::from_utf8_lossy(&output.stdout).lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[5], serde_json::json!({ "tagName": TAGS[2], "timestamp": "2024-01-01T00:00:02.0000000-08:00", "value": 2, "quality": 192 }));
+    let endpoints: Vec<String> = canary.requests().into_iter().map(|(endpoint, _)| endpoint).collect();
+    assert_eq!(endpoints, ["getLiveDataToken", "getLiveData", "getLiveData", "getLiveData", "revokeLiveDataToken"]);
+    let continuations: Vec<Value> = canary.requests().into_iter().filter(|(endpoint, _)| endpoint == "getLiveData").map(|(_, body)| body["continuation"].clone()).collect();
+    assert_eq!(continuations, [Value::Null, Value::from(1), Value::from(2)]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Summary: 3 polls, 6 samples written."));
+}
+
+#[test]
+fn retries_an_empty_browse() {
+    let canary = MockCanary::start(MockConfig { tags: canary_tags(), empty_browses: 1, ..Default::default() });
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "csv", &["--retry_on_empty", "2"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("retrying in 1s (attempt 1 of 2)"));
+    assert_eq!(csv::Reader::from_path(path).unwrap().records().count(), TAGS.len());
+
+    let canary = MockCanary::start(MockConfig { tags: canary_tags(), empty_browses: 1, ..Default::default() });
+    let (output, _) = export(&canary, dir.path(), "csv", &[]);
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[test]
+fn sends_correlation_ids_and_records_them_in_the_audit_log() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let audit_log = dir.path().join("audit.jsonl");
+    let (output, _) = export(&canary, dir.path(), "csv", &["--audit_log", audit_log.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let run_id = stdout.lines().find_map(|line| line.strip_prefix("Run ID: ")).unwrap().trim_end_matches('.');
+    let expected = [format!("{}-1", run_id), format!("{}-2", run_id)];
+    assert_eq!(canary.correlation_ids(), expected);
+    let audited: Vec<String> = std::fs::read_to_string(audit_log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["correlation_id"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(audited, expected);
+}
+
+#[test]
+fn data_subcommand_requests_aggregates() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hourly.csv");
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Day", "--aggregate", "TimeAverage2", "--aggregate_interval", "1:00:00", "--output_format", "csv", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let (_, body) = canary.requests().pop().unwrap();
+    assert_eq!((body["aggregateName"].as_str(), body["aggregateInterval"].as_str()), (Some("TimeAverage2"), Some("1:00:00")));
+    let mut reader = csv::Reader::from_path(&path).unwrap();
+    assert_eq!(reader.headers().unwrap(), vec!["tag_name", "timestamp", "value", "quality", "quality_name", "aggregate"]);
+    assert!(reader.records().all(|row| &row.unwrap()[5] == "TimeAverage2"));
+
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Day", "--aggregate", "TimeAverage2", "--output_format", "csv", "--output_file", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn takes_connection_flags_from_a_config_profile() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let config_dir = dir.path().join("canary-context");
+    std::fs::create_dir(&config_dir).unwrap();
+    let config = format!(
+        "default_profile = \"dev\"\n\n[profiles.dev]\ncanary = \"{}\"\napi_token = \"{}\"\napplication = \"From Profile\"\n\n[profiles.prod]\ncanary = [\"http://127.0.0.1:1\", \"{}\"]\napi_token = \"{}\"\n",
+        canary.url, common::TOKEN, canary.url, common::TOKEN
+    );
+    std::fs::write(config_dir.join("config.toml"), config).unwrap();
+    let run = |args: &[&str]| std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).env("XDG_CONFIG_HOME", dir.path()).args(args).output().unwrap();
+
+    let output = run(&["browse"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), TAGS.len());
+    assert_eq!(canary.requests().last().unwrap().1["application"], "From Profile");
+
+    let output = run(&["--profile", "prod", "browse", "--application", "From Flag"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("http://127.0.0.1:1 is unreachable"));
+    assert_eq!(canary.requests().last().unwrap().1["application"], "From Flag");
+
+    let output = run(&["browse", "--api_token", "wrong"]);
+    assert!(!output.status.success());
+    assert!(!run(&["--help"]).stdout.windows(common::TOKEN.len()).any(|window| window == common::TOKEN.as_bytes()));
+
+    let output = run(&["--profile", "staging", "browse"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown profile 'staging'; the config file defines: dev, prod"));
+    let output = run(&["--config", dir.path().join("missing.toml").to_str().unwrap(), "version"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot read config file"));
+}
+
+#[test]
+fn takes_subcommand_defaults_from_the_profile() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    let write_config = |tables: &str| std::fs::write(&config, format!("default_profile = \"dev\"\n\n[profiles.dev]\ncanary = \"{}\"\napi_token = \"{}\"\n\n{}", canary.url, common::TOKEN, tables)).unwrap();
+    let run = |args: &[&str]| std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).args(["--config", config.to_str().unwrap()]).args(args).output().unwrap();
+    let data_file = dir.path().join("data.out");
+    write_config("[profiles.dev.data]\noutput_format = \"json\"\nmax-size = 1\nretries = 0\n\n[profiles.dev.tags]\noutput_format = \"csv\"\n");
+
+    let output = run(&["data", TAGS[0], "--start_time", "Now-1Hour", "--output_file", data_file.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(serde_json::from_str::<Value>(&std::fs::read_to_string(&data_file).unwrap()).is_ok());
+    assert_eq!(canary.requests().pop().unwrap().1["maxSize"], 1);
+
+    let output = run(&["data", TAGS[0], "--start_time", "Now-1Hour", "--output_file", data_file.to_str().unwrap(), "--output_format", "txt"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(std::fs::read_to_string(&data_file).unwrap().starts_with("TagName: "));
+
+    let output = run(&["export", "--output_file", dir.path().join("tags.csv").to_str().unwrap()]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--output_format"), "{}", String::from_utf8_lossy(&output.stderr));
+
+    write_config("[profiles.dev.data]\nformat = \"json\"\n");
+    assert!(String::from_utf8_lossy(&run(&["browse"]).stderr).contains("unknown flag 'format' in the profile's [data] table"));
+    write_config("[profiles.dev.exports]\noutput_format = \"json\"\n");
+    assert!(String::from_utf8_lossy(&run(&["browse"]).stderr).contains("unknown subcommand [exports] in profile"));
+    write_config("apitoken = \"x\"\n");
+    assert!(String::from_utf8_lossy(&run(&["browse"]).stderr).contains("unknown profile setting 'apitoken'"));
+}
+
+#[test]
+fn self_test_passes_without_a_server() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).arg("self_test").output().unwrap();
+    assert!(output.status.success(), "{}\n{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("FAIL"), "{}", stdout);
+    assert!(["argument parsing", "context batching", "data paging", "csv writer", "sqlite writer"].iter().all(|check| stdout.contains(&format!("PASS  {}", check))), "{}", stdout);
+    assert!(stdout.contains("Self-test passed: "));
+}
+
+#[test]
+fn features_lists_formats_sinks_and_what_is_missing() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).arg("features").output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with(&format!("canary-context {} (", env!("CARGO_PKG_VERSION"))), "{}", stdout);
+    assert!(stdout.contains("Output formats: csv, txt, json, ndjson, xlsx, arrow, sqlite\n"), "{}", stdout);
+    assert!(stdout.contains("Compression: gzip, zstd\n"), "{}", stdout);
+    assert!(stdout.contains("TLS: native-tls ("), "{}", stdout);
+    assert!(stdout.contains("Not in this build: Parquet, Kafka, OPC UA\n"), "{}", stdout);
+}
+
+#[test]
+fn examples_use_the_profile_server_and_hide_its_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, format!("[profiles.prod]\ncanary = \"https://canary-prod:55236\"\napi_token = \"{}\"\n", common::TOKEN)).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).args(["--config", config.to_str().unwrap(), "--profile", "prod", "examples"]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let commands: Vec<&str> = stdout.lines().filter(|line| line.starts_with("canary-context ")).collect();
+    assert!(commands.len() >= 10);
+    assert!(commands.iter().all(|command| command.starts_with("canary-context --profile prod --canary https://canary-prod:55236 ")));
+    assert!(!stdout.contains(common::TOKEN) && !stdout.contains("--api_token"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).args(["--config", config.to_str().unwrap(), "examples"]).output().unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains("canary-context --canary https://canary:55236 --api_token '<token>' browse\n"));
+}
+
+#[test]
+fn environment_variables_sit_between_flags_and_profiles() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, "default_profile = \"dev\"\n\n[profiles.dev]\ncanary = \"http://127.0.0.1:1\"\napi_token = \"from-profile\"\napplication = \"From Profile\"\n").unwrap();
+    let run = |args: &[&str], env: &[(&str, &str)]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).env("CANARY_CONFIG", &config).envs(env.iter().copied()).args(args).arg("browse").output().unwrap()
+    };
+
+    let output = run(&[], &[("CANARY_URL", &canary.url), ("CANARY_API_TOKEN", common::TOKEN), ("CANARY_TIMEZONE", "UTC")]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let body = canary.requests().last().unwrap().1.clone();
+    assert_eq!((body["application"].as_str(), body["timezone"].as_str()), (Some("From Profile"), Some("UTC")));
+
+    let output = run(&["--api_token", "from-flag"], &[("CANARY_URL", &canary.url), ("CANARY_API_TOKEN", common::TOKEN)]);
+    assert!(!output.status.success());
+    assert_eq!(canary.requests().last().unwrap().1["apiToken"], "from-flag");
+
+    let output = run(&["--help"], &[("CANARY_API_TOKEN", common::TOKEN)]);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains(common::TOKEN));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context")).env("CANARY_CONFIG", &config).env("CANARY_API_TOKEN", common::TOKEN).arg("examples").output().unwrap();
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("--api_token"));
+}
+
+#[test]
+fn logs_in_with_a_username_and_password() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let audit = dir.path().join("audit.jsonl");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+        .args(["--canary", &canary.url, "--username", common::USERNAME, "--audit_log", audit.to_str().unwrap(), "browse"])
+        .env("CANARY_PASSWORD", common::PASSWORD)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), TAGS.len());
+
+    let requests = canary.requests();
+    assert_eq!(requests[0].0, "getUserToken");
+    assert_eq!((requests[1].0.as_str(), &requests[1].1["userToken"]), ("browseTags", &Value::from(format!("{}-1", common::USER_TOKEN))));
+    assert_eq!(requests.last().unwrap().0, "revokeUserToken");
+    assert!(requests[1].1.get("apiToken").is_none());
+    let log = std::fs::read_to_string(&audit).unwrap();
+    assert!(!log.contains(common::PASSWORD) && !log.contains(common::USER_TOKEN));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+        .args(["--canary", &canary.url, "--username", common::USERNAME, "--password", "wrong", "browse"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("getUserToken returned HTTP 401"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+        .args(["--canary", &canary.url, "--username", common::USERNAME, "browse"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--password or CANARY_PASSWORD is required"));
+}
+
+#[test]
+fn logs_in_again_when_the_user_token_expires() {
+    let canary = MockCanary::start(MockConfig { tags: canary_tags(), user_token_uses: Some(1), ..Default::default() });
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("export.csv");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+        .args(["--canary", &canary.url, "--username", common::USERNAME, "--password", common::PASSWORD])
+        .args(["export", "--output_format", "csv", "--output_file", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(csv::Reader::from_path(&path).unwrap().records().count(), TAGS.len());
+
+    let calls: Vec<(String, Value)> = canary.requests().into_iter().map(|(endpoint, body)| (endpoint, body["userToken"].clone())).collect();
+    let token = |n: usize| Value::from(format!("{}-{}", common::USER_TOKEN, n));
+    assert_eq!(calls, [
+        ("getUserToken".to_string(), Value::Null),
+        ("browseTags".to_string(), token(1)),
+        ("getTagContext".to_string(), token(1)),
+        ("getUserToken".to_string(), Value::Null),
+        ("getTagContext".to_string(), token(2)),
+        ("revokeUserToken".to_string(), token(2)),
+    ]);
+}
+
+#[test]
+fn revokes_the_user_token_when_an_export_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let export = |canary: &MockCanary, format: &str, path: &std::path::Path| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+            .args(["--canary", &canary.url, "--username", common::USERNAME, "--password", common::PASSWORD])
+            .args(["export", "--output_format", format, "--output_file", path.to_str().unwrap()])
+            .output()
+            .unwrap()
+    };
+    let revokes = |canary: &MockCanary| canary.requests().iter().filter(|(endpoint, _)| endpoint == "revokeUserToken").count();
+
+    // NDJSON is opened after the browse, while the session is in use.
+    let canary = MockCanary::with_tags(&TAGS);
+    let output = export(&canary, "ndjson", &dir.path().join("missing").join("export.ndjson"));
+    assert!(!output.status.success());
+    assert_eq!(canary.requests().last().unwrap().0, "revokeUserToken");
+    assert_eq!(revokes(&canary), 1);
+
+    let canary = MockCanary::with_tags(&TAGS);
+    let output = export(&canary, "csv", &dir.path().join("export.csv"));
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(revokes(&canary), 1);
+}
+
+#[test]
+fn revokes_a_user_token() {
+    let canary = MockCanary::with_tags(&TAGS);
+    canary.add_user_token("leaked-token");
+    let revoke = |token: &str, stdin: &str| {
+        let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_canary-context"))
+            .args(["--canary", &canary.url, "revoke_token", token])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(&mut child.stdin.take().unwrap(), stdin.as_bytes()).unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    let output = revoke("-", "leaked-token\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Token revoked"));
+    assert_eq!(canary.requests().last().unwrap(), &("revokeUserToken".to_string(), serde_json::json!({ "userToken": "leaked-token" })));
+
+    let output = revoke("leaked-token", "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("revokeUserToken returned HTTP 401"));
+}
+
+#[test]
+fn writes_xlsx_with_a_frozen_header_row() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), "xlsx", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let mut workbook = zip::ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap();
+    let mut part = |name: &str| std::io::read_to_string(workbook.by_name(name).unwrap()).unwrap();
+    let sheet = part("xl/worksheets/sheet1.xml");
+    assert!(sheet.contains(r#"ySplit="1""#) && sheet.contains(r#"state="frozen""#), "{}", sheet);
+    assert!(sheet.contains("<cols>"));
+    assert!(sheet.contains(r#"<c r="A4""#) && !sheet.contains(r#"<c r="A5""#));
+    let strings = part("xl/sharedStrings.xml");
+    assert!(strings.contains("<t>tag_name</t>") && strings.contains("<t>retrieved_at</t>"));
+    assert!(TAGS.iter().all(|tag| strings.contains(&format!("<t>{}</t>", tag))));
+
+    let output = common::run_cli(&canary, &["export", "--output_format", "xlsx", "--output_file", "unused.xlsx", "--encoding", "utf-16le"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("xlsx output has no text encoding"));
+
+    // Timestamps become dates; 2024-01-01T00:00:00-08:00 is day 45292.
+    let (output, path) = export(&canary, dir.path(), "xlsx", &["--locale", "de-DE"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut workbook = zip::ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap();
+    let mut part = |name: &str| std::io::read_to_string(workbook.by_name(name).unwrap()).unwrap();
+    assert!(part("xl/worksheets/sheet1.xml").contains("<v>45292</v>"));
+    assert!(part("xl/styles.xml").contains(r#"formatCode="dd.mm.yyyy hh:mm:ss""#));
+    assert!(!part("xl/sharedStrings.xml").contains("2024-01-01T00:00:00"));
+
+    let output = common::run_cli(&canary, &["export", "--output_format", "csv", "--output_file", "unused.csv", "--locale", "de-DE"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--locale only applies to xlsx output"));
+}
+
+#[test]
+fn writes_arrow_ipc_with_typed_columns() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, TimestampMicrosecondType};
+
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let read = |path: &std::path::Path| {
+        let reader = arrow_ipc::reader::FileReader::try_new(std::fs::File::open(path).unwrap(), None).unwrap();
+        reader.map(Result::unwrap).collect::<Vec<_>>().remove(0)
+    };
+
+    let (output, path) = export(&canary, dir.path(), "arrow", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let batch = read(&path);
+    let names: Vec<&str> = batch.schema_ref().fields().iter().map(|field| field.name().as_str()).collect();
+    assert_eq!(names, ["tag_name", "historian_item_id", "source_item_id", "oldest_time_stamp", "latest_time_stamp", "retrieved_at"]);
+    assert_eq!(batch.num_rows(), TAGS.len());
+    assert_eq!(batch.column(0).as_string::<i32>().value(0), TAGS[0]);
+    assert!(batch.column(2).is_null(1));
+    assert_eq!(batch.column(3).as_primitive::<TimestampMicrosecondType>().value(0), 1_704_096_000_000_000);
+    let metadata = batch.schema_ref().metadata();
+    assert_eq!(metadata["server"], canary.url);
+    assert_eq!(metadata["api_version"], "api/v2");
+    assert_eq!(metadata["tool_version"], env!("CARGO_PKG_VERSION"));
+    let run_id = String::from_utf8_lossy(&output.stdout).split("Run ID: ").nth(1).unwrap().split('.').next().unwrap().to_string();
+    assert_eq!(metadata["run_id"], run_id);
+    let query: serde_json::Value = serde_json::from_str(&metadata["query"]).unwrap();
+    assert_eq!(query["search"], "");
+
+    let path = dir.path().join("data.arrow");
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "arrow", "--output_file", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let batch = read(&path);
+    assert_eq!(batch.column(2).as_primitive::<Float64Type>().values().to_vec(), [1.5, 2.5]);
+    assert_eq!(batch.column(4).as_string::<i32>().value(1), "Bad: Comm Failure");
+    let query: serde_json::Value = serde_json::from_str(&batch.schema_ref().metadata()["query"]).unwrap();
+    assert_eq!(query["tags"], TAGS[0]);
+    assert_eq!(query["start_time"], "Now-1Hour");
+}
+
+#[test]
+fn compresses_output_by_flag_or_extension() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let run = |format: &str, file: &std::path::Path, extra: &[&str]| common::run_cli(&canary, &[&["export", "--output_format", format, "--output_file", file.to_str().unwrap()], extra].concat());
+
+    let path = dir.path().join("tags.csv.gz");
+    let output = run("csv", &path, &[]);
+    assert!(output.status.success());
+    let csv_bytes = std::io::read_to_string(flate2::read::GzDecoder::new(std::fs::File::open(&path).unwrap())).unwrap();
+    assert_eq!(csv::Reader::from_reader(csv_bytes.as_bytes()).records().count(), TAGS.len());
+    let size = std::fs::metadata(&path).unwrap().len();
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!(", {} bytes ({} uncompressed, ratio {:.1}:1).", size, csv_bytes.len(), csv_bytes.len() as f64 / size as f64)), "{}", String::from_utf8_lossy(&output.stdout));
+
+    let path = dir.path().join("tags.json");
+    assert!(run("json", &path, &["--compress", "zstd"]).status.success());
+    let json: Value = serde_json::from_slice(&zstd::decode_all(std::fs::File::open(&path).unwrap()).unwrap()).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), TAGS.len());
+
+    let path = dir.path().join("tags.arrow.zst");
+    assert!(run("arrow", &path, &[]).status.success());
+    let bytes = zstd::decode_all(std::fs::File::open(&path).unwrap()).unwrap();
+    let reader = arrow_ipc::reader::FileReader::try_new(std::io::Cursor::new(bytes), None).unwrap();
+    assert_eq!(reader.map(|batch| batch.unwrap().num_rows()).sum::<usize>(), TAGS.len());
+
+    // One gzip stream across every batch, flushed after each.
+    let output = run("ndjson", std::path::Path::new("-"), &["--compress", "gzip", "--batch_size", "1"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::io::read_to_string(flate2::read::GzDecoder::new(output.stdout.as_slice())).unwrap().lines().count(), TAGS.len());
+
+    let path = dir.path().join("tags.txt.gz");
+    assert!(run("txt", &path, &["--compress", "none"]).status.success());
+    assert!(std::fs::read_to_string(&path).unwrap().starts_with("TagName: "));
+
+    let output = run("sqlite", &dir.path().join("tags.db.gz"), &[]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sqlite output cannot be compressed"));
+}
+
+#[test]
+fn upserts_context_and_data_into_sqlite() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tags.db");
+    let db = path.to_str().unwrap();
+    let output = common::run_cli(&canary, &["export", "--output_format", "sqlite", "--output_file", db]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    canary.set_tags(&[TAGS[0].to_string(), "Plant9.New".to_string()]);
+    let output = common::run_cli(&canary, &["export", "--output_format", "sqlite", "--output_file", db]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "sqlite", "--output_file", db]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = common::run_cli(&canary, &["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "sqlite", "--output_file", db]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let mut names: Vec<String> = connection.prepare("SELECT tag_name FROM tag_context ORDER BY tag_name").unwrap().query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+    let mut expected: Vec<String> = TAGS.iter().map(|tag| tag.to_string()).chain(["Plant9.New".to_string()]).collect();
+    names.sort();
+    expected.sort();
+    assert_eq!(names, expected);
+    let samples: Vec<(String, f64, i64, String)> = connection
+        .prepare("SELECT timestamp, value, quality, quality_name FROM tag_data ORDER BY timestamp")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(samples, [("2024-01-01T00:00:00.0000000-08:00".to_string(), 1.5, 192, "Good".to_string()), ("2024-01-01T00:01:00.0000000-08:00".to_string(), 2.5, 24, "Bad: Comm Failure".to_string())]);
+
+    let output = common::run_cli(&canary, &["tags", "--output_format", "sqlite", "--output_file", db]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use export or data"));
+}
+
+#[test]
+fn leaves_the_sqlite_inventory_unchanged_when_a_run_fails() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tags.db");
+    let db = path.to_str().unwrap();
+    let output = common::run_cli(&canary, &["export", "--output_format", "sqlite", "--output_file", db]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let inventory = || -> Vec<(String, String)> {
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let mut rows = connection.prepare("SELECT tag_name, latest_time_stamp FROM tag_context ORDER BY tag_name").unwrap();
+        rows.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(Result::unwrap).collect()
+    };
+    let before = inventory();
+
+    // The second run updates every row and adds new ones, then fails at commit.
+    let new_tags: Vec<String> = (0..200).map(|i| format!("Plant9.Tag{}", i)).chain(TAGS.iter().map(|tag| tag.to_string())).collect();
+    canary.set_tags(&new_tags);
+    canary.set_latest_time_stamp("2025-06-01T00:00:00.0000000Z");
+    let size = std::fs::metadata(&path).unwrap().len().to_string();
+    let output = common::run_cli(&canary, &["export", "--output_format", "sqlite", "--output_file", db, "--max_output_bytes", &size]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("would exceed the maximum output size"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(inventory(), before);
+}
+
+#[test]
+fn versions_the_sqlite_schema() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tags.db");
+    let db = path.to_str().unwrap();
+    let version = || rusqlite::Connection::open(&path).unwrap().query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0)).unwrap();
+    let data = |extra: &[&str]| common::run_cli(&canary, &[&["data", TAGS[0], "--start_time", "Now-1Hour", "--output_format", "sqlite", "--output_file", db][..], extra].concat());
+
+    // A database written before the schema was versioned has no tag_data yet.
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute_batch("CREATE TABLE tag_context (tag_name TEXT PRIMARY KEY NOT NULL, historian_item_id TEXT, source_item_id TEXT, oldest_time_stamp TEXT NOT NULL, latest_time_stamp TEXT NOT NULL, retrieved_at TEXT NOT NULL, historian TEXT, row_id TEXT)")
+        .unwrap();
+    let output = data(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has schema version 0, older than the version 2 this release writes; rerun with --auto_migrate"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(version(), 0);
+    let output = data(&["--auto_migrate"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(version(), 2);
+    let output = common::run_cli(&canary, &["export", "--output_format", "sqlite", "--output_file", db]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    rusqlite::Connection::open(&path).unwrap().pragma_update(None, "user_version", 3).unwrap();
+    let output = common::run_cli(&canary, &["export", "--output_format", "sqlite", "--output_file", db, "--auto_migrate"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has schema version 3, newer than the version 2 this release writes"), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn writes_txt_and_csv_in_legacy_encodings() {
+    let canary = MockCanary::with_tags(&["Plant1.Temp\u{B0}C", "Plant1.\u{20AC}Rate", "Plant1.\u{3A9}"]);
+    let dir = tempfile::tempdir().unwrap();
+    let contains = |bytes: &[u8], needle: &[u8]| bytes.windows(needle.len()).any(|window| window == needle);
+
+    let (output, path) = export(&canary, dir.path(), "txt", &["--encoding", "windows-1252"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let bytes = std::fs::read(path).unwrap();
+    assert!(bytes.starts_with(b"TagName: Plant1.Temp\xB0C\n"));
+    assert!(contains(&bytes, b"TagName: Plant1.\x80Rate\n"));
+    assert!(contains(&bytes, b"TagName: Plant1.?\n"));
+
+    let (output, path) = export(&canary, dir.path(), "csv", &["--encoding", "utf-16le", "--bom"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let bytes = std::fs::read(path).unwrap();
+    assert_eq!(&bytes[..2], b"\xFF\xFE");
+    let units: Vec<u16> = bytes[2..].chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    let text = String::from_utf16(&units).unwrap();
+    assert!(text.starts_with("tag_name,historian_item_id,") && text.contains("Plant1.\u{3A9},"));
+
+    let (output, _) = export(&canary, dir.path(), "json", &["--encoding", "utf-16le"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("JSON output is always UTF-8"));
+    assert_eq!(canary.requests().len(), 4, "a rejected --encoding must fail before calling the server");
+}
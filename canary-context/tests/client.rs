@@ -0,0 +1,138 @@
+#![cfg(feature = "integration-tests")]
+
+mod common;
+
+use canary_context::audit::AuditLog;
+use canary_context::request::{BrowseRequest, TagDataRequest};
+use canary_context::response::ResponseError;
+use canary_context::{CallError, CallErrorKind, CanaryClient};
+use common::{MockCanary, MockConfig, PASSWORD, TOKEN, USERNAME};
+use serde_json::Value;
+use std::error::Error;
+use std::time::Duration;
+
+const TAGS: [&str; 2] = ["Plant1.Line1.Temperature", "Plant1.Line2.Flow"];
+
+#[tokio::test]
+async fn browses_and_fetches_context_as_a_library() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let client = CanaryClient::builder(TOKEN).server("http://127.0.0.1:1").server(canary.url.as_str()).connect().await.unwrap();
+    assert_eq!(client.server(), canary.url);
+    assert_eq!(client.skipped_servers(), ["http://127.0.0.1:1"]);
+
+    let tags = client.browse_tags(&BrowseRequest::builder().deep(true).build()).await.unwrap();
+    assert_eq!(tags, TAGS);
+    let contexts = client.get_tag_context(&tags).await.unwrap();
+    assert_eq!(contexts.iter().map(|context| context.tag_name()).collect::<Vec<_>>(), TAGS);
+    assert!(format!("{:?}", client).contains("[REDACTED]") && !format!("{:?}", client).contains(TOKEN));
+}
+
+#[tokio::test]
+async fn fetches_context_in_batches() {
+    let tags: Vec<String> = (0..5).map(|i| format!("Plant1.Line{}.Temperature", i)).collect();
+    let canary = MockCanary::with_tags(&tags.iter().map(String::as_str).collect::<Vec<_>>());
+    let client = CanaryClient::builder(TOKEN).server(canary.url.as_str()).context_batch_size(2).connect().await.unwrap();
+    let contexts = client.get_tag_context(&tags).await.unwrap();
+    assert_eq!(contexts.iter().map(|context| context.tag_name()).collect::<Vec<_>>(), tags);
+    let batches: Vec<usize> = canary.requests().iter().filter(|(endpoint, _)| endpoint == "getTagContext").map(|(_, body)| body["tags"].as_array().unwrap().len()).collect();
+    assert_eq!(batches, [2, 2, 1]);
+
+    let done = std::sync::Mutex::new(Vec::new());
+    client.get_tag_context_with_progress(&tags, |tags| done.lock().unwrap().push(tags)).await.unwrap();
+    let mut done = done.into_inner().unwrap();
+    done.sort();
+    assert_eq!(done, [1, 2, 2]);
+}
+
+#[tokio::test]
+async fn continues_data_reads_past_the_server_size_limit() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let client = CanaryClient::builder(TOKEN).server(canary.url.as_str()).connect().await.unwrap();
+    let request = TagDataRequest::builder().tags(TAGS).start_time("Now-1Hour").include_bounds(true).max_size(3).build();
+    let data = client.get_tag_data(&request).await.unwrap();
+    assert_eq!(data.iter().map(|tag| tag.tag_name()).collect::<Vec<_>>(), TAGS);
+    let timestamps: Vec<&str> = data[1].values().iter().map(|sample| sample.timestamp()).collect();
+    assert_eq!(timestamps, ["2023-12-31T23:59:30.0000000-08:00", "2024-01-01T00:00:00.0000000-08:00", "2024-01-01T00:01:00.0000000-08:00", "2024-01-01T00:02:30.0000000-08:00"]);
+    let continuations: Vec<Value> = canary.requests().into_iter().filter(|(endpoint, _)| endpoint == "getTagData").map(|(_, body)| body["continuation"].clone()).collect();
+    assert_eq!(continuations, [Value::Null, Value::from(3)]);
+}
+
+#[tokio::test]
+async fn fetches_batches_concurrently_up_to_the_limit() {
+    let tags: Vec<String> = (0..12).map(|i| format!("Plant1.Line{}.Temperature", i)).collect();
+    let canary = MockCanary::start(MockConfig { tags: tags.clone(), response_delay: Some(Duration::from_millis(100)), ..Default::default() });
+    let client = CanaryClient::builder(TOKEN).server(canary.url.as_str()).context_batch_size(1).concurrency(3).connect().await.unwrap();
+    let contexts = client.get_tag_context(&tags).await.unwrap();
+    assert_eq!(contexts.iter().map(|context| context.tag_name()).collect::<Vec<_>>(), tags);
+    assert_eq!(canary.max_concurrent_calls(), 3);
+}
+
+#[tokio::test]
+async fn spaces_calls_to_the_rate_limit() {
+    let tags: Vec<String> = (0..6).map(|i| format!("Plant1.Line{}.Temperature", i)).collect();
+    let canary = MockCanary::start(MockConfig { tags: tags.clone(), ..Default::default() });
+    let client = CanaryClient::builder(TOKEN).server(canary.url.as_str()).context_batch_size(1).concurrency(4).rate_limit(Some(20.0)).connect().await.unwrap();
+    let started = std::time::Instant::now();
+    client.get_tag_context(&tags).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(250), "{:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn keeps_an_idle_user_token_alive_until_closed() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let dir = tempfile::tempdir().unwrap();
+    let audit_log = dir.path().join("audit.jsonl");
+    let client = CanaryClient::builder_for_user(USERNAME, PASSWORD)
+        .server(canary.url.as_str())
+        .keep_alive_interval(Duration::from_millis(50))
+        .audit_log(AuditLog::open(audit_log.to_str()).unwrap())
+        .connect()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    client.close().await.unwrap();
+    let keep_alives = canary.requests().iter().filter(|(endpoint, _)| endpoint == "keepAlive").count();
+    assert!(keep_alives >= 2, "{} keep-alives", keep_alives);
+    // Keep-alives are audited and correlated like every other call; one
+    // still in flight when the client closes is not.
+    let audited: Vec<Value> = std::fs::read_to_string(&audit_log).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let audited_keep_alives = audited.iter().filter(|record| record["endpoint"] == "keepAlive").count();
+    assert!(audited_keep_alives >= keep_alives - 1 && audited_keep_alives >= 2, "{} of {} keep-alives audited", audited_keep_alives, keep_alives);
+    let correlation_ids = canary.correlation_ids();
+    assert!(correlation_ids.iter().all(|id| id.starts_with(client.run_id())), "{:?}", correlation_ids);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let endpoints: Vec<String> = canary.requests().into_iter().map(|(endpoint, _)| endpoint).collect();
+    assert_eq!(endpoints.last().map(String::as_str), Some("revokeUserToken"));
+    assert!(client.browse_tags(&BrowseRequest::builder().build()).await.is_err());
+}
+
+#[tokio::test]
+async fn counts_logins_and_keep_alives_against_the_request_limit() {
+    let canary = MockCanary::with_tags(&TAGS);
+    let client = CanaryClient::builder_for_user(USERNAME, PASSWORD)
+        .server(canary.url.as_str())
+        .keep_alive_interval(Duration::from_millis(50))
+        .max_requests(Some(2))
+        .connect()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let error = client.browse_tags(&BrowseRequest::builder().build()).await.unwrap_err();
+    assert!(error.to_string().contains("the limit of 2 API calls"), "{}", error);
+    client.close().await.unwrap();
+    let endpoints: Vec<String> = canary.requests().into_iter().map(|(endpoint, _)| endpoint).collect();
+    assert_eq!(endpoints, ["getUserToken", "keepAlive", "revokeUserToken"]);
+}
+
+#[tokio::test]
+async fn stops_reading_a_response_past_the_size_limit() {
+    // The body never ends, so the call only returns if the client gives up
+    // at the limit instead of buffering the whole body.
+    let canary = MockCanary::start(MockConfig { endless_responses: true, ..Default::default() });
+    let client = CanaryClient::builder(TOKEN).server(canary.url.as_str()).max_response_size(Some(64 * 1024)).connect().await.unwrap();
+    let error = tokio::time::timeout(Duration::from_secs(10), client.browse_tags(&BrowseRequest::builder().build())).await.expect("the body was read past the limit").unwrap_err();
+    let error = error.downcast_ref::<CallError>().expect("a call error");
+    assert!(matches!(error.source().and_then(|source| source.downcast_ref()), Some(ResponseError::TooLarge { endpoint: "browseTags", limit: 65536 })), "{}", error);
+    assert_eq!(error.kind(), CallErrorKind::Unreadable);
+}
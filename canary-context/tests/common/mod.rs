@@ -0,0 +1,538 @@
+//! In-process mock of the Canary Views API and helpers for running the CLI
+//! binary against it.
+
+// Each test crate uses a different subset of these helpers.
+#![allow(dead_code)]
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures_util::{stream, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::{Path as FsPath, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const TOKEN: &str = "integration-token";
+pub const LIVE_TOKEN: &str = "live-session-token";
+pub const USERNAME: &str = "operator";
+pub const PASSWORD: &str = "operator-password";
+pub const USER_TOKEN: &str = "user-token";
+
+#[derive(Clone, Default)]
+pub struct MockConfig {
+    pub tags: Vec<String>,
+    /// Respond with this status to every request instead of data.
+    pub fail_with: Option<u16>,
+    /// Tags that browse lists but getTagContext leaves out of its response.
+    pub without_context: Vec<String>,
+    /// Tags whose getTagContext batch is answered with a 500.
+    pub failing_context: Vec<String>,
+    /// Number of browseTags calls answered with no tags before the real list.
+    pub empty_browses: usize,
+    /// Calls each user token is accepted for before it expires.
+    pub user_token_uses: Option<usize>,
+    /// Statuses to answer the next calls with, one each, before serving
+    /// normally again.
+    pub transient_failures: Vec<u16>,
+    /// Time each call takes to answer once its credentials are checked.
+    pub response_delay: Option<Duration>,
+    /// Answer every call but getUserToken with a 200 body that never ends.
+    pub endless_responses: bool,
+    /// Tags whose getTagData series lacks its last sample.
+    pub missing_samples: Vec<String>,
+    /// Most samples per tag a getTagData page holds, whatever maxSize asks.
+    pub max_page_size: Option<usize>,
+    /// Latest timestamp getTagContext reports for every tag instead of the
+    /// fixed one.
+    pub latest_time_stamp: Option<String>,
+}
+
+/// Issued user tokens with the calls each is still accepted for.
+type UserTokens = Vec<(String, Option<usize>)>;
+
+#[derive(Clone)]
+struct MockState {
+    config: Arc<Mutex<MockConfig>>,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+    correlation_ids: Arc<Mutex<Vec<String>>>,
+    live_tags: Arc<Mutex<Vec<String>>>,
+    user_tokens: Arc<Mutex<UserTokens>>,
+    issued_user_tokens: Arc<Mutex<usize>>,
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+/// Calls being answered now and the most answered at once.
+#[derive(Default)]
+struct InFlight {
+    current: usize,
+    max: usize,
+}
+
+pub struct MockCanary {
+    pub url: String,
+    config: Arc<Mutex<MockConfig>>,
+    user_tokens: Arc<Mutex<UserTokens>>,
+    requests: Arc<Mutex<Vec<(String, Value)>>>,
+    correlation_ids: Arc<Mutex<Vec<String>>>,
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+impl MockCanary {
+    pub fn start(config: MockConfig) -> Self {
+        let config = Arc::new(Mutex::new(config));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let correlation_ids = Arc::new(Mutex::new(Vec::new()));
+        let user_tokens: Arc<Mutex<UserTokens>> = Arc::default();
+        let in_flight: Arc<Mutex<InFlight>> = Arc::default();
+        let state = MockState { config: config.clone(), requests: requests.clone(), correlation_ids: correlation_ids.clone(), live_tags: Arc::default(), user_tokens: user_tokens.clone(), issued_user_tokens: Arc::default(), in_flight: in_flight.clone() };
+        let app = Router::new().route("/api/v2/:endpoint", post(handle)).with_state(state);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app).await.unwrap();
+            });
+        });
+
+        MockCanary { url, config, user_tokens, requests, correlation_ids, in_flight }
+    }
+
+    pub fn with_tags(tags: &[&str]) -> Self {
+        Self::start(MockConfig { tags: tags.iter().map(|tag| tag.to_string()).collect(), ..Default::default() })
+    }
+
+    /// Replaces the tag namespace served from now on.
+    pub fn set_tags(&self, tags: &[String]) {
+        self.config.lock().unwrap().tags = tags.to_vec();
+    }
+
+    /// Replaces the latest timestamp reported for every tag from now on.
+    pub fn set_latest_time_stamp(&self, timestamp: &str) {
+        self.config.lock().unwrap().latest_time_stamp = Some(timestamp.to_string());
+    }
+
+    /// Endpoints called so far, in order, with their JSON bodies.
+    pub fn requests(&self) -> Vec<(String, Value)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Makes `token` a valid user token, as if getUserToken had issued it.
+    pub fn add_user_token(&self, token: &str) {
+        self.user_tokens.lock().unwrap().push((token.to_string(), None));
+    }
+
+    /// Most calls that were answered at the same time, with a `response_delay`.
+    pub fn max_concurrent_calls(&self) -> usize {
+        self.in_flight.lock().unwrap().max
+    }
+
+    /// X-Correlation-ID headers of the calls so far, in order.
+    pub fn correlation_ids(&self) -> Vec<String> {
+        self.correlation_ids.lock().unwrap().clone()
+    }
+}
+
+async fn handle(State(state): State<MockState>, Path(endpoint): Path<String>, headers: HeaderMap, Json(body): Json<Value>) -> Response {
+    state.requests.lock().unwrap().push((endpoint.clone(), body.clone()));
+    let correlation_id = headers.get("X-Correlation-ID").and_then(|value| value.to_str().ok()).unwrap_or_default();
+    state.correlation_ids.lock().unwrap().push(correlation_id.to_string());
+    let config = {
+        let mut config = state.config.lock().unwrap();
+        let snapshot = config.clone();
+        if endpoint == "browseTags" {
+            config.empty_browses = config.empty_browses.saturating_sub(1);
+        }
+        if !config.transient_failures.is_empty() {
+            config.transient_failures.remove(0);
+        }
+        snapshot
+    };
+
+    if let Some(status) = config.transient_failures.first() {
+        return (StatusCode::from_u16(*status).unwrap(), "<html><body>mock transient failure</body></html>").into_response();
+    }
+    if let Some(status) = config.fail_with {
+        return (StatusCode::from_u16(status).unwrap(), "<html><body>mock failure</body></html>").into_response();
+    }
+    if config.endless_responses && endpoint != "getUserToken" {
+        let head = stream::once(async { Ok::<_, Infallible>(Bytes::from_static(b"{\"statusCode\":\"Good\",\"tags\":[\"")) });
+        let padding = stream::repeat_with(|| Ok(Bytes::from(vec![b'a'; 8192])));
+        return Body::from_stream(head.chain(padding)).into_response();
+    }
+    if endpoint == "getUserToken" {
+        if body["username"] != USERNAME || body["password"] != PASSWORD {
+            return (StatusCode::UNAUTHORIZED, Json(json!({ "statusCode": "BadUnauthorized", "errors": ["invalid credentials"] }))).into_response();
+        }
+        let issued = {
+            let mut issued = state.issued_user_tokens.lock().unwrap();
+            *issued += 1;
+            *issued
+        };
+        let token = format!("{}-{}", USER_TOKEN, issued);
+        state.user_tokens.lock().unwrap().push((token.clone(), config.user_token_uses));
+        return Json(json!({ "statusCode": "Good", "errors": [], "userToken": token })).into_response();
+    }
+    let user_token_valid = {
+        let mut tokens = state.user_tokens.lock().unwrap();
+        let valid = tokens.iter_mut().find(|(token, _)| body["userToken"] == token.as_str()).is_some_and(|(_, uses)| match uses {
+            _ if endpoint == "revokeUserToken" => true,
+            Some(0) => false,
+            Some(uses) => {
+                *uses -= 1;
+                true
+            }
+            None => true,
+        });
+        if valid && endpoint == "revokeUserToken" {
+            tokens.retain(|(token, _)| body["userToken"] != token.as_str());
+        }
+        valid
+    };
+    if body["apiToken"] != TOKEN && !user_token_valid {
+        return (StatusCode::UNAUTHORIZED, Json(json!({ "statusCode": "BadUnauthorized", "errors": ["invalid token"] }))).into_response();
+    }
+
+    if let Some(delay) = config.response_delay {
+        {
+            let mut in_flight = state.in_flight.lock().unwrap();
+            in_flight.current += 1;
+            in_flight.max = in_flight.max.max(in_flight.current);
+        }
+        tokio::time::sleep(delay).await;
+        state.in_flight.lock().unwrap().current -= 1;
+    }
+
+    let requested = |body: &Value| -> Vec<String> {
+        body["tags"].as_array().into_iter().flatten().filter_map(|tag| tag.as_str().map(String::from)).collect()
+    };
+    match endpoint.as_str() {
+        "browseTags" => {
+            let path = body["path"].as_str().unwrap_or_default();
+            let prefix = if path.is_empty() { String::new() } else { format!("{}.", path) };
+            let deep = body["deep"].as_bool().unwrap_or(true);
+            let search = body["search"].as_str().unwrap_or_default().to_lowercase();
+            let tags: Vec<&String> = config
+                .tags
+                .iter()
+                .filter(|_| config.empty_browses == 0)
+                .filter(|tag| tag.strip_prefix(&prefix).is_some_and(|rest| deep || !rest.contains('.')))
+                .filter(|tag| tag.to_lowercase().contains(&search))
+                .collect();
+            Json(json!({ "statusCode": "Good", "errors": [], "tags": tags })).into_response()
+        }
+        "getTagContext" if requested(&body).iter().any(|tag| config.failing_context.contains(tag)) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, "<html><body>mock batch failure</body></html>").into_response()
+        }
+        "getTagContext" => {
+            let data: Vec<Value> = requested(&body)
+                .iter()
+                .filter(|tag| config.tags.contains(tag) && !config.without_context.contains(tag))
+                .map(|tag| {
+                    let mut context = tag_context(&config.tags, tag);
+                    if let Some(latest) = &config.latest_time_stamp {
+                        context["tagContext"]["latestTimeStamp"] = json!(latest);
+                    }
+                    context
+                })
+                .collect();
+            Json(json!({ "statusCode": "Good", "errors": [], "data": data })).into_response()
+        }
+        "getTagData" => {
+            let data: Vec<(String, Vec<Value>)> = requested(&body)
+                .into_iter()
+                .map(|tag| {
+                    let mut samples = vec![
+                        json!({ "t": "2024-01-01T00:00:00.0000000-08:00", "v": 1.5, "q": 192 }),
+                        json!({ "t": "2024-01-01T00:01:00.0000000-08:00", "v": 2.5, "q": 24 }),
+                    ];
+                    if body["includeBounds"] == true {
+                        samples.insert(0, json!({ "t": "2023-12-31T23:59:30.0000000-08:00", "v": 1.0, "q": 192 }));
+                        samples.push(json!({ "t": "2024-01-01T00:02:30.0000000-08:00", "v": 3.0, "q": 192 }));
+                    }
+                    if config.missing_samples.contains(&tag) {
+                        samples.pop();
+                    }
+                    (tag, samples)
+                })
+                .collect();
+            // Pages of maxSize samples per tag, continued from the sample index.
+            let start = body["continuation"].as_u64().unwrap_or_default() as usize;
+            let page_size = body["maxSize"].as_u64().map_or(usize::MAX, |size| size as usize).min(config.max_page_size.unwrap_or(usize::MAX));
+            let more = data.iter().any(|(_, samples)| samples.len() > start.saturating_add(page_size));
+            let data: serde_json::Map<String, Value> = data.into_iter().map(|(tag, samples)| (tag, samples.into_iter().skip(start).take(page_size).collect())).collect();
+            let continuation = if more { json!(start + page_size) } else { Value::Null };
+            Json(json!({ "statusCode": "Good", "errors": [], "data": data, "continuation": continuation })).into_response()
+        }
+        "getQualities" => {
+            // Names the server's own way, so tests can tell them from the built-in ones.
+            let qualities: serde_json::Map<String, Value> = body["qualities"].as_array().unwrap().iter().map(|code| (code.to_string(), json!(format!("Server quality {}", code)))).collect();
+            Json(json!({ "statusCode": "Good", "errors": [], "qualities": qualities })).into_response()
+        }
+        "getLiveDataToken" => {
+            *state.live_tags.lock().unwrap() = requested(&body);
+            Json(json!({ "statusCode": "Good", "errors": [], "liveDataToken": LIVE_TOKEN })).into_response()
+        }
+        "getLiveData" => {
+            if body["liveDataToken"] != LIVE_TOKEN {
+                return (StatusCode::BAD_REQUEST, Json(json!({ "statusCode": "BadRequest", "errors": ["unknown live data token"] }))).into_response();
+            }
+            // Each poll reports one sample per tag, valued with the poll's sequence number.
+            let poll = body["continuation"].as_u64().unwrap_or_default();
+            let data: serde_json::Map<String, Value> = state.live_tags.lock().unwrap()
+                .iter()
+                .map(|tag| (tag.clone(), json!([{ "t": format!("2024-01-01T00:00:{:02}.0000000-08:00", poll), "v": poll, "q": 192 }])))
+                .collect();
+            Json(json!({ "statusCode": "Good", "errors": [], "data": data, "continuation": poll + 1 })).into_response()
+        }
+        "revokeLiveDataToken" | "keepAlive" | "revokeUserToken" => Json(json!({ "statusCode": "Good", "errors": [] })).into_response(),
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Deterministic context for a tag: odd-indexed tags have no source item ID.
+pub fn tag_context(tags: &[String], tag: &str) -> Value {
+    let index = tags.iter().position(|candidate| candidate == tag).unwrap_or_default();
+    json!({
+        "tagName": tag,
+        "tagContext": {
+            "historianItemId": format!("hist-{}", index),
+            "sourceItemId": if index % 2 == 0 { Value::String(format!("src-{}", index)) } else { Value::Null },
+            "oldestTimeStamp": "2024-01-01T00:00:00.0000000-08:00",
+            "latestTimeStamp": "2024-06-01T12:00:00.0000000-07:00"
+        }
+    })
+}
+
+/// Runs the CLI against `canary` with the standard connection flags plus `args`.
+pub fn run_cli(canary: &MockCanary, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_canary-context"))
+        .args(["--canary", &canary.url, "--api_token", TOKEN])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+pub fn export(canary: &MockCanary, dir: &FsPath, format: &str, extra: &[&str]) -> (Output, PathBuf) {
+    let path = dir.join(format!("export.{}", format));
+    let mut args = vec!["export", "--output_format", format, "--output_file", path.to_str().unwrap()];
+    args.extend_from_slice(extra);
+    (run_cli(canary, &args), path)
+}
+
+/// Query parameters and body of each insert, in order.
+type Inserts = Arc<Mutex<Vec<(HashMap<String, String>, String)>>>;
+
+/// Authorization header of each insert, in order.
+type Authorizations = Arc<Mutex<Vec<Option<String>>>>;
+
+/// Mock of the ClickHouse HTTP interface that accepts every insert and
+/// records its query parameters, body and credentials.
+pub struct MockClickHouse {
+    pub url: String,
+    inserts: Inserts,
+    authorizations: Authorizations,
+}
+
+impl MockClickHouse {
+    pub fn start() -> Self {
+        let inserts = Arc::new(Mutex::new(Vec::new()));
+        let authorizations = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new().route("/", post(insert)).with_state((inserts.clone(), authorizations.clone()));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, app).await.unwrap();
+            });
+        });
+
+        MockClickHouse { url, inserts, authorizations }
+    }
+
+    /// Inserts received so far: query parameters and JSONEachRow body.
+    pub fn inserts(&self) -> Vec<(HashMap<String, String>, String)> {
+        self.inserts.lock().unwrap().clone()
+    }
+
+    /// The Authorization header of each insert received so far.
+    pub fn authorizations(&self) -> Vec<Option<String>> {
+        self.authorizations.lock().unwrap().clone()
+    }
+}
+
+async fn insert(State((inserts, authorizations)): State<(Inserts, Authorizations)>, Query(params): Query<HashMap<String, String>>, headers: HeaderMap, body: String) -> StatusCode {
+    authorizations.lock().unwrap().push(headers.get("authorization").map(|value| value.to_str().unwrap().to_string()));
+    inserts.lock().unwrap().push((params, body));
+    StatusCode::OK
+}
+
+/// Minimal Redis stand-in that speaks enough RESP for pipelined writes:
+/// every command is recorded and answered with an integer, or `+OK` for
+/// connection setup commands.
+pub struct MockRedis {
+    pub url: String,
+    commands: Arc<Mutex<Vec<Vec<String>>>>,
+}
+
+impl MockRedis {
+    pub fn start() -> Self {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let log = commands.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let log = log.clone();
+                std::thread::spawn(move || serve_redis(stream.unwrap(), log));
+            }
+        });
+        MockRedis { url, commands }
+    }
+
+    /// Commands received so far, each as its arguments.
+    pub fn commands(&self) -> Vec<Vec<String>> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+fn serve_redis(stream: std::net::TcpStream, log: Arc<Mutex<Vec<Vec<String>>>>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let read_line = |reader: &mut BufReader<std::net::TcpStream>| {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok().filter(|read| *read > 0).map(|_| line.trim_end().to_string())
+    };
+    while let Some(header) = read_line(&mut reader) {
+        let count: usize = header.trim_start_matches('*').parse().unwrap();
+        let mut command = Vec::new();
+        for _ in 0..count {
+            let len: usize = read_line(&mut reader).unwrap().trim_start_matches('$').parse().unwrap();
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).unwrap();
+            command.push(String::from_utf8_lossy(&arg[..len]).into_owned());
+        }
+        let reply: &[u8] = if matches!(command[0].to_uppercase().as_str(), "HSET" | "HDEL" | "EXPIRE") { b":1\r\n" } else { b"+OK\r\n" };
+        log.lock().unwrap().push(command);
+        writer.write_all(reply).unwrap();
+    }
+}
+
+/// QuestDB stand-in for line protocol over TCP: records the lines of each
+/// connection once the client closes it.
+pub struct MockQuestDb {
+    pub addr: String,
+    connections: Arc<Mutex<Vec<Vec<String>>>>,
+}
+
+impl MockQuestDb {
+    pub fn start() -> Self {
+        use std::io::BufRead;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let log = connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let received: Vec<String> = std::io::BufReader::new(stream.unwrap()).lines().map(Result::unwrap).collect();
+                log.lock().unwrap().push(received);
+            }
+        });
+        MockQuestDb { addr, connections }
+    }
+
+    /// Lines of the first connection, waiting briefly for it to be read
+    /// since the client may exit before this side sees it closed.
+    pub fn lines(&self) -> Vec<String> {
+        for _ in 0..50 {
+            if let Some(lines) = self.connections.lock().unwrap().first() {
+                return lines.clone();
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("no QuestDB connection was closed");
+    }
+}
+
+/// Minimal NATS server with a JetStream-style stream behind every subject:
+/// each published message is recorded and acked on its reply subject.
+pub struct MockNats {
+    pub url: String,
+    messages: Arc<Mutex<Vec<NatsMessage>>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NatsMessage {
+    pub subject: String,
+    pub headers: String,
+    pub payload: String,
+}
+
+impl MockNats {
+    pub fn start() -> Self {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let log = messages.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let log = log.clone();
+                std::thread::spawn(move || serve_nats(stream.unwrap(), log));
+            }
+        });
+        MockNats { url, messages }
+    }
+
+    /// Messages published so far, in order.
+    pub fn messages(&self) -> Vec<NatsMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+fn serve_nats(stream: std::net::TcpStream, log: Arc<Mutex<Vec<NatsMessage>>>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    writer.write_all(b"INFO {\"server_id\":\"mock\",\"version\":\"2.10.0\",\"proto\":1,\"headers\":true,\"max_payload\":1048576}\r\n").unwrap();
+    let mut inbox_sid = String::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+        let parts: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+        line.clear();
+        match parts.first().map(String::as_str) {
+            Some("PING") => writer.write_all(b"PONG\r\n").unwrap(),
+            Some("SUB") => inbox_sid = parts.last().unwrap().clone(),
+            Some("HPUB") => {
+                let (header_len, total_len): (usize, usize) = (parts[parts.len() - 2].parse().unwrap(), parts[parts.len() - 1].parse().unwrap());
+                let mut data = vec![0; total_len + 2];
+                reader.read_exact(&mut data).unwrap();
+                let mut log = log.lock().unwrap();
+                log.push(NatsMessage {
+                    subject: parts[1].clone(),
+                    headers: String::from_utf8_lossy(&data[..header_len]).into_owned(),
+                    payload: String::from_utf8_lossy(&data[header_len..total_len]).into_owned(),
+                });
+                if parts.len() == 5 {
+                    let ack = format!("{{\"stream\":\"CANARY\",\"seq\":{}}}", log.len());
+                    writer.write_all(format!("MSG {} {} {}\r\n{}\r\n", parts[2], inbox_sid, ack.len(), ack).as_bytes()).unwrap();
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,51 @@
+#![cfg(feature = "integration-tests")]
+
+mod common;
+
+use common::{export, MockCanary};
+
+/// Includes names that need quoting or escaping in at least one format.
+const FIXTURE: [&str; 4] = ["Plant1.Line1.Temperature", "Plant1.Line1.Pressure", "Plant,2.\"Quoted\" Tag", "Usine/Débit €"];
+
+fn golden(format: &str) -> Vec<u8> {
+    let canary = MockCanary::with_tags(&FIXTURE);
+    let dir = tempfile::tempdir().unwrap();
+    let (output, path) = export(&canary, dir.path(), format, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    redact_run_timestamps(std::fs::read(path).unwrap())
+}
+
+/// Replaces the per-run `retrieved_at` value (`YYYY-MM-DDTHH:MM:SSZ`) so
+/// snapshots are stable; server timestamps have a different shape.
+fn redact_run_timestamps(mut bytes: Vec<u8>) -> Vec<u8> {
+    const SHAPE: &[u8; 20] = b"0000-00-00T00:00:00Z";
+    let mut i = 0;
+    while i + SHAPE.len() <= bytes.len() {
+        let window = &bytes[i..i + SHAPE.len()];
+        if window.iter().zip(SHAPE).all(|(b, s)| if *s == b'0' { b.is_ascii_digit() } else { b == s }) {
+            bytes.splice(i..i + SHAPE.len(), *b"<retrieved_at>");
+        }
+        i += 1;
+    }
+    bytes
+}
+
+#[test]
+fn csv_output() {
+    insta::assert_binary_snapshot!(".csv", golden("csv"));
+}
+
+#[test]
+fn txt_output() {
+    insta::assert_binary_snapshot!(".txt", golden("txt"));
+}
+
+#[test]
+fn json_output() {
+    insta::assert_binary_snapshot!(".json", golden("json"));
+}
+
+#[test]
+fn ndjson_output() {
+    insta::assert_binary_snapshot!(".ndjson", golden("ndjson"));
+}
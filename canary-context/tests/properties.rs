@@ -0,0 +1,53 @@
+#![cfg(feature = "integration-tests")]
+
+mod common;
+
+use common::{export, MockCanary};
+use proptest::prelude::*;
+
+/// Tag names mixing path separators with characters that need quoting or
+/// escaping: commas, quotes, line breaks, tabs and non-ASCII text.
+fn tag_names() -> impl Strategy<Value = Vec<String>> {
+    let name = "[A-Za-z0-9 ._/,;\"'\t\r\n\\\\=+@éü€漢-]{1,24}";
+    prop::collection::hash_set(name, 1..8).prop_map(|names| names.into_iter().collect())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn csv_round_trips_tag_names(tags in tag_names()) {
+        let canary = MockCanary::with_tags(&[]);
+        canary.set_tags(&tags);
+        let dir = tempfile::tempdir().unwrap();
+        let (output, path) = export(&canary, dir.path(), "csv", &[]);
+        prop_assert!(output.status.success());
+
+        let mut reader = csv::Reader::from_path(path).unwrap();
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        prop_assert_eq!(rows.len(), tags.len());
+        for (row, tag) in rows.iter().zip(&tags) {
+            let expected = if tag.starts_with(['=', '+', '-', '@', '\t', '\r']) { format!("'{}", tag) } else { tag.clone() };
+            prop_assert_eq!(row.len(), 6);
+            prop_assert_eq!(&row[0], expected.as_str());
+        }
+    }
+
+    #[test]
+    fn txt_keeps_one_line_per_field(tags in tag_names()) {
+        let canary = MockCanary::with_tags(&[]);
+        canary.set_tags(&tags);
+        let dir = tempfile::tempdir().unwrap();
+        let (output, path) = export(&canary, dir.path(), "txt", &[]);
+        prop_assert!(output.status.success());
+
+        let text = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = text.split('\n').collect();
+        prop_assert_eq!(lines.len(), tags.len() * 7 + 1);
+        for (record, tag) in lines.chunks(7).zip(&tags) {
+            let expected = format!("TagName: {}", tag.replace('\r', "\\r").replace('\n', "\\n"));
+            prop_assert_eq!(record[0], expected.as_str());
+            prop_assert!(record[1].starts_with("  HistorianItemId: "));
+        }
+    }
+}